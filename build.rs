@@ -0,0 +1,61 @@
+use std::{env, fs::{self, File}, io::{Result, Write}, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=USER_BIN_DIR");
+    insert_app_data().unwrap();
+}
+
+/// Embeds every file in `USER_BIN_DIR` (default `user/bin`) into the kernel
+/// image, sorted by name, through a generated `link_app.S`.
+fn insert_app_data() -> Result<()> {
+    let bin_dir = env::var("USER_BIN_DIR").unwrap_or_else(|_| String::from("user/bin"));
+    let bin_dir = PathBuf::from(bin_dir);
+    println!("cargo:rerun-if-changed={}", bin_dir.display());
+
+    let mut apps: Vec<(String, PathBuf)> = fs::read_dir(&bin_dir)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::canonicalize(&path).unwrap()))
+                .collect()
+        })
+        .unwrap_or_default();
+    apps.sort();
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut f = File::create(out_dir.join("link_app.S"))?;
+
+    writeln!(f, ".align 3")?;
+    writeln!(f, ".section .data")?;
+    writeln!(f, ".globl _num_app")?;
+    writeln!(f, "_num_app:")?;
+    writeln!(f, "  .quad {}", apps.len())?;
+    for i in 0..apps.len() {
+        writeln!(f, "  .quad app_{}_start", i)?;
+    }
+    if !apps.is_empty() {
+        writeln!(f, "  .quad app_{}_end", apps.len() - 1)?;
+    }
+
+    writeln!(f)?;
+    writeln!(f, ".globl _app_names")?;
+    writeln!(f, "_app_names:")?;
+    for (name, _) in apps.iter() {
+        writeln!(f, "  .string \"{}\"", name)?;
+    }
+
+    for (i, (_, path)) in apps.iter().enumerate() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        writeln!(f)?;
+        writeln!(f, ".section .data")?;
+        writeln!(f, ".globl app_{}_start", i)?;
+        writeln!(f, ".globl app_{}_end", i)?;
+        writeln!(f, ".align 3")?;
+        writeln!(f, "app_{}_start:", i)?;
+        writeln!(f, "  .incbin \"{}\"", path.display())?;
+        writeln!(f, "app_{}_end:", i)?;
+    }
+
+    Ok(())
+}
@@ -1,14 +1,21 @@
 CARGO = cargo
 
 MODE ?= debug
+SCHED ?= rr
 export LOG ?= info
+export USER_BIN_DIR ?= $(WORK_DIR)/user/bin
 
 BIN = $(TARGET_DIR)/$(TARGET)/$(MODE)/$(PROJECT)
+USER_DIR = $(WORK_DIR)/user
 
-build:
-	$(CARGO) build
+build: user
+	$(CARGO) build --features sched-$(SCHED)
+
+user:
+	$(MAKE) -C $(USER_DIR) build BIN_DIR=$(USER_BIN_DIR)
 
 clean:
 	$(CARGO) clean
+	$(MAKE) -C $(USER_DIR) clean BIN_DIR=$(USER_BIN_DIR)
 
-.PHONY: build clean
+.PHONY: build user clean
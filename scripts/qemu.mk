@@ -5,12 +5,22 @@ BIOS = -bios default
 MOMERY = -m 128M
 GRAPHIC = -nographic
 
+SWAP_IMG = $(TARGET_DIR)/swap.img
+SWAP_SIZE_MB ?= 32
+SWAP = -drive file=$(SWAP_IMG),if=none,format=raw,id=swap0 \
+			 -device virtio-blk-device,drive=swap0
+
 QEMU_FLAGS = $(MACHINE) \
 						 $(BIOS) \
 						 $(MOMERY) \
-						 $(GRAPHIC)
+						 $(GRAPHIC) \
+						 $(SWAP)
+
+$(SWAP_IMG):
+	@mkdir -p $(TARGET_DIR)
+	dd if=/dev/zero of=$@ bs=1M count=$(SWAP_SIZE_MB)
 
-run: build
+run: build $(SWAP_IMG)
 	$(QEMU) $(QEMU_FLAGS) -kernel $(BIN)
 
 GDBINIT_TEMPLATE = $(WORK_DIR)/.gdbinit.template
@@ -18,7 +28,7 @@ GDBINIT_FILE = $(WORK_DIR)/.gdbinit
 GDB_PORT = 27000
 GDB_QEMU_FLAGS = -S -gdb tcp::$(GDB_PORT)
 
-gdb: build
+gdb: build $(SWAP_IMG)
 	@grep -E "set auto-load safe-path /" ~/.gdbinit || echo "set auto-load safe-path /" >> ~/.gdbinit
 	@cp $(GDBINIT_TEMPLATE) $(GDBINIT_FILE)
 	@echo "target remote localhost:$(GDB_PORT)\nfile $(BIN)" >> $(GDBINIT_FILE)
@@ -10,7 +10,25 @@ pub const PA_WIDTH: usize = PA_WIDTH_SV39;
 pub const VPN_WIDTH: usize = VA_WIDTH - PAGE_SIZE_BITS;
 pub const PPN_WIDTH: usize = PA_WIDTH - PAGE_SIZE_BITS;
 
-pub const MEMORY_END: usize = 0x88000000;
-
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+
+pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE * 2;
+pub const USER_STACK_SIZE: usize = PAGE_SIZE * 16;
+pub const USER_STACK_TOP: usize = (1 << (VA_WIDTH - 1)) - PAGE_SIZE;
+/// User-executable page holding the `rt_sigreturn` stub signal handlers return to.
+pub const SIGRETURN_TRAMPOLINE: usize = USER_STACK_TOP;
+
+pub const CLOCK_FREQ: usize = 10_000_000;
+pub const TICKS_PER_SEC: usize = 100;
+
+/// Device registers the kernel maps, as `(base, size)`, on QEMU's virt machine.
+pub const MMIO: &[(usize, usize)] = &[
+    (0x1000_1000, 0x1000), // virtio-mmio slot 0
+];
+pub const VIRTIO0: usize = 0x1000_1000;
+
+/// Pages are swapped out once free frames drop below the low watermark,
+/// until they are back above the high one.
+pub const SWAP_LOW_WATERMARK: usize = 256;
+pub const SWAP_HIGH_WATERMARK: usize = 512;
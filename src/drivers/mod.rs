@@ -1,3 +1,5 @@
 pub use timer::{get_time, set_next_trigger};
+pub use virtio_blk::{SECTOR_SIZE, VIRTIO_BLOCK};
 
 mod timer;
+mod virtio_blk;
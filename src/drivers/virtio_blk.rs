@@ -0,0 +1,208 @@
+use core::{ptr::{read_volatile, write_volatile}, sync::atomic::{fence, Ordering}};
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::{config::{PAGE_SIZE, VIRTIO0}, sync::UPIntrFreeCell};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+// Legacy virtio-mmio registers, as QEMU exposes them by default.
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_GUEST_FEATURES: usize = 0x020;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_CONFIG: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// A request takes three descriptors, and only one is in flight at a time.
+const QUEUE_SIZE: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// The legacy layout puts the used ring on the page after the rest.
+#[repr(C, align(4096))]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[repr(C, align(4096))]
+struct VirtQueue {
+    desc: [Descriptor; QUEUE_SIZE],
+    avail: AvailRing,
+    used: UsedRing,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Everything the device reads or writes has to sit at its physical
+/// address, so it lives in `.bss` rather than on a kernel stack.
+static mut QUEUE: VirtQueue = unsafe { core::mem::zeroed() };
+static mut HEADER: RequestHeader = RequestHeader { kind: 0, reserved: 0, sector: 0 };
+static mut STATUS: u8 = 0;
+
+/// A polled driver for a legacy virtio-mmio block device.
+pub struct VirtIOBlock {
+    base: usize,
+    last_used_idx: u16,
+    /// In sectors.
+    capacity: usize,
+}
+
+lazy_static! {
+    pub static ref VIRTIO_BLOCK: Option<UPIntrFreeCell<VirtIOBlock>> =
+        VirtIOBlock::probe(VIRTIO0).map(|device| unsafe { UPIntrFreeCell::new(device) });
+}
+
+impl VirtIOBlock {
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Sets up the device at `base`, if there is a block device there.
+    fn probe(base: usize) -> Option<Self> {
+        let mut device = Self { base, last_used_idx: 0, capacity: 0 };
+
+        if device.read_reg(REG_MAGIC) != VIRTIO_MAGIC || device.read_reg(REG_DEVICE_ID) != VIRTIO_DEVICE_BLOCK {
+            info!("no virtio block device at {:#x}", base);
+            return None;
+        }
+        if device.read_reg(REG_VERSION) != 1 {
+            warn!("virtio block device at {:#x} is not a legacy one", base);
+            return None;
+        }
+
+        device.write_reg(REG_STATUS, 0);
+        device.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE);
+        device.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        device.write_reg(REG_GUEST_FEATURES, 0);
+        device.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+
+        device.write_reg(REG_QUEUE_SEL, 0);
+        if (device.read_reg(REG_QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            warn!("virtio block device at {:#x} has a queue that is too short", base);
+            return None;
+        }
+        device.write_reg(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        device.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+        let queue_pa = core::ptr::addr_of!(QUEUE) as usize;
+        device.write_reg(REG_QUEUE_PFN, (queue_pa / PAGE_SIZE) as u32);
+
+        device.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+
+        device.capacity = device.read_reg(REG_CONFIG) as usize | (device.read_reg(REG_CONFIG + 4) as usize) << 32;
+        info!("virtio block device at {:#x} with {} sectors", base, device.capacity);
+
+        Some(device)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Reads whole sectors from `sector` on into `buf`, which must be
+    /// identity mapped.
+    pub fn read_sectors(&mut self, sector: usize, buf: &mut [u8]) {
+        self.request(VIRTIO_BLK_T_IN, sector, buf.as_mut_ptr() as usize, buf.len());
+    }
+
+    /// Writes `buf`, which must be identity mapped, to the sectors from
+    /// `sector` on.
+    pub fn write_sectors(&mut self, sector: usize, buf: &[u8]) {
+        self.request(VIRTIO_BLK_T_OUT, sector, buf.as_ptr() as usize, buf.len());
+    }
+
+    fn request(&mut self, kind: u32, sector: usize, buf_pa: usize, len: usize) {
+        assert!(len.is_multiple_of(SECTOR_SIZE) && sector + len / SECTOR_SIZE <= self.capacity);
+
+        unsafe {
+            HEADER = RequestHeader { kind, reserved: 0, sector: sector as u64 };
+            STATUS = 0xff;
+
+            let queue = &mut *core::ptr::addr_of_mut!(QUEUE);
+            let buf_flags = if kind == VIRTIO_BLK_T_IN { VIRTQ_DESC_F_WRITE } else { 0 };
+            queue.desc[0] = Descriptor {
+                addr: core::ptr::addr_of!(HEADER) as u64,
+                len: core::mem::size_of::<RequestHeader>() as u32,
+                flags: VIRTQ_DESC_F_NEXT,
+                next: 1,
+            };
+            queue.desc[1] = Descriptor { addr: buf_pa as u64, len: len as u32, flags: buf_flags | VIRTQ_DESC_F_NEXT, next: 2 };
+            queue.desc[2] = Descriptor { addr: core::ptr::addr_of!(STATUS) as u64, len: 1, flags: VIRTQ_DESC_F_WRITE, next: 0 };
+
+            let avail_idx = read_volatile(&queue.avail.idx);
+            write_volatile(&mut queue.avail.ring[avail_idx as usize % QUEUE_SIZE], 0);
+            fence(Ordering::SeqCst);
+            write_volatile(&mut queue.avail.idx, avail_idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+            self.write_reg(REG_QUEUE_NOTIFY, 0);
+
+            while read_volatile(&queue.used.idx) == self.last_used_idx {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+            self.last_used_idx = self.last_used_idx.wrapping_add(1);
+            self.write_reg(REG_INTERRUPT_ACK, self.read_reg(REG_INTERRUPT_STATUS));
+
+            let status = read_volatile(core::ptr::addr_of!(STATUS));
+            assert_eq!(status, VIRTIO_BLK_S_OK, "virtio block request on sector {} failed", sector);
+        }
+    }
+}
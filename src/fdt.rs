@@ -0,0 +1,192 @@
+//! Just enough of a flattened device tree reader to find out where RAM is.
+
+use alloc::vec::Vec;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// `struct fdt_header`, ten big-endian words.
+const HEADER_SIZE: usize = 40;
+
+/// A device tree blob the firmware left in memory.
+pub struct Fdt {
+    base: usize,
+    total_size: usize,
+    off_dt_struct: usize,
+    off_dt_strings: usize,
+    off_mem_rsvmap: usize,
+}
+
+/// `#address-cells` and `#size-cells` of a node, which say how the `reg`
+/// of its children is laid out.
+#[derive(Clone, Copy)]
+struct Cells {
+    address: usize,
+    size: usize,
+}
+
+impl Default for Cells {
+    fn default() -> Self {
+        Self { address: 2, size: 1 }
+    }
+}
+
+impl Fdt {
+    /// Reads the header of the blob at physical address `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be readable, and stay so while the `Fdt` is in use.
+    pub unsafe fn from_addr(base: usize) -> Option<Self> {
+        if base == 0 || !base.is_multiple_of(4) {
+            return None;
+        }
+        let mut fdt = Self { base, total_size: HEADER_SIZE, off_dt_struct: 0, off_dt_strings: 0, off_mem_rsvmap: 0 };
+        if fdt.read_u32(0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = fdt.read_u32(4)? as usize;
+        if total_size < HEADER_SIZE {
+            return None;
+        }
+        fdt.off_dt_struct = fdt.read_u32(8)? as usize;
+        fdt.off_dt_strings = fdt.read_u32(12)? as usize;
+        fdt.off_mem_rsvmap = fdt.read_u32(16)? as usize;
+        fdt.total_size = total_size;
+        Some(fdt)
+    }
+
+    /// Where the blob itself sits, as `[start, end)`.
+    pub fn range(&self) -> (usize, usize) {
+        (self.base, self.base + self.total_size)
+    }
+
+    /// The `len` bytes at `offset`, if they are inside the blob.
+    fn bytes(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        if offset.checked_add(len)? > self.total_size {
+            return None;
+        }
+        Some(unsafe { core::slice::from_raw_parts((self.base + offset) as *const u8, len) })
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&self, offset: usize) -> Option<u64> {
+        Some(u64::from_be_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    /// A NUL-terminated string, which has to end inside the blob.
+    fn read_str(&self, offset: usize) -> Option<&str> {
+        let rest = self.bytes(offset, self.total_size.checked_sub(offset)?)?;
+        let len = rest.iter().position(|&byte| byte == 0)?;
+        Some(core::str::from_utf8(&rest[..len]).unwrap_or(""))
+    }
+
+    /// Reads a number made of `cells` 32-bit cells.
+    fn read_cells(&self, offset: usize, cells: usize) -> Option<usize> {
+        (0..cells).try_fold(0, |value, i| Some(value << 32 | self.read_u32(offset + i * 4)? as usize))
+    }
+
+    /// Calls `f` with the path of every node that has a `reg` property,
+    /// for each `(address, size)` pair in it. The root is the empty name
+    /// at the start of the path. A broken blob is walked up to where it
+    /// stops making sense.
+    fn for_each_reg(&self, mut f: impl FnMut(&[&str], usize, usize)) {
+        let _ = self.walk_regs(&mut f);
+    }
+
+    fn walk_regs(&self, f: &mut impl FnMut(&[&str], usize, usize)) -> Option<()> {
+        // Names of the nodes we are in, with their own cells.
+        let mut path: Vec<(&str, Cells)> = Vec::new();
+        let mut offset = self.off_dt_struct;
+
+        loop {
+            let token = self.read_u32(offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = self.read_str(offset)?;
+                    offset += (name.len() + 1).next_multiple_of(4);
+                    path.push((name, Cells::default()));
+                }
+                FDT_END_NODE => {
+                    path.pop();
+                }
+                FDT_PROP => {
+                    let len = self.read_u32(offset)? as usize;
+                    let name = self.read_str(self.off_dt_strings.checked_add(self.read_u32(offset + 4)? as usize)?)?;
+                    let value = offset + 8;
+                    self.bytes(value, len)?;
+                    offset = value + len.next_multiple_of(4);
+
+                    // Properties belong to a node.
+                    let (_, cells) = path.last_mut()?;
+                    match name {
+                        "#address-cells" => cells.address = self.read_u32(value)? as usize,
+                        "#size-cells" => cells.size = self.read_u32(value)? as usize,
+                        "reg" if path.len() >= 2 => {
+                            let cells = path[path.len() - 2].1;
+                            let entry = (cells.address + cells.size) * 4;
+                            if entry == 0 {
+                                continue;
+                            }
+                            let names: Vec<&str> = path.iter().map(|&(name, _)| name).collect();
+                            for start in (value..value + len - len % entry).step_by(entry) {
+                                let address = self.read_cells(start, cells.address)?;
+                                let size = self.read_cells(start + cells.address * 4, cells.size)?;
+                                f(&names, address, size);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => return Some(()),
+                // Anything else means the blob is broken; stop here.
+                _ => return None,
+            }
+        }
+    }
+
+    /// The `reg` of every `/memory` node, as `[start, end)`.
+    pub fn memory_regions(&self) -> Vec<(usize, usize)> {
+        let mut regions = Vec::new();
+        self.for_each_reg(|path, address, size| {
+            if path.len() == 2 && (path[1] == "memory" || path[1].starts_with("memory@")) && size != 0 {
+                regions.push((address, address.saturating_add(size)));
+            }
+        });
+        regions
+    }
+
+    /// The memory reservation block and the static children of
+    /// `/reserved-memory`, as `[start, end)`.
+    pub fn reserved_regions(&self) -> Vec<(usize, usize)> {
+        let mut regions = Vec::new();
+
+        let mut offset = self.off_mem_rsvmap;
+        // A missing terminator ends the block at the end of the blob.
+        while let (Some(address), Some(size)) = (self.read_u64(offset), self.read_u64(offset + 8)) {
+            let (address, size) = (address as usize, size as usize);
+            if address == 0 && size == 0 {
+                break;
+            }
+            regions.push((address, address.saturating_add(size)));
+            offset += 16;
+        }
+
+        self.for_each_reg(|path, address, size| {
+            if path.len() == 3 && path[1] == "reserved-memory" && size != 0 {
+                regions.push((address, address.saturating_add(size)));
+            }
+        });
+        regions
+    }
+}
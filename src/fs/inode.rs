@@ -0,0 +1,141 @@
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}, vec, vec::Vec};
+use bitflags::bitflags;
+use lazy_static::lazy_static;
+use log::debug;
+
+use crate::{loader::get_app_data_mut_by_name, mm::{PageCache, UserBuffer}, sync::UPIntrFreeCell};
+
+use super::File;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 6;
+        const TRUNC = 1 << 9;
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+impl OpenFlags {
+    /// Returns `(readable, writable)`.
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.contains(Self::RDWR) {
+            (true, true)
+        } else if self.contains(Self::WRONLY) {
+            (false, true)
+        } else {
+            (true, false)
+        }
+    }
+}
+
+lazy_static! {
+    /// One page cache per file, shared by every open and mapping of it.
+    /// It is only held weakly here, so its frames go once the last file
+    /// handle or mapping does.
+    static ref PAGE_CACHES: UPIntrFreeCell<BTreeMap<&'static str, Weak<PageCache>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+/// An open file of the root directory, which holds the program images
+/// linked into the kernel. They are read and written through the page
+/// cache, and keep their size.
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    offset: UPIntrFreeCell<usize>,
+    page_cache: Arc<PageCache>,
+}
+
+impl OSInode {
+    pub fn new(readable: bool, writable: bool, page_cache: Arc<PageCache>) -> Self {
+        Self {
+            readable,
+            writable,
+            offset: unsafe { UPIntrFreeCell::new(0) },
+            page_cache,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.page_cache.size()
+    }
+
+    pub fn read_all(&self) -> Vec<u8> {
+        let mut offset = self.offset.exclusive_access();
+        let mut v = vec![0; self.size().saturating_sub(*offset)];
+        *offset += self.page_cache.read_at(*offset, &mut v);
+        v
+    }
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total = 0;
+        for buffer in buf {
+            let len = self.page_cache.read_at(*offset, buffer);
+            *offset += len;
+            total += len;
+            if len < buffer.len() {
+                break;
+            }
+        }
+        total
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total = 0;
+        for buffer in buf {
+            let len = self.page_cache.write_at(*offset, buffer);
+            *offset += len;
+            total += len;
+            if len < buffer.len() {
+                break;
+            }
+        }
+        total
+    }
+
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        Some(self.page_cache.clone())
+    }
+}
+
+/// Resolves `path` in the root directory. Leading `/` and `./` are
+/// accepted, there are no subdirectories. Files cannot be created or
+/// change size.
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    let name = path.trim_start_matches('/').trim_start_matches("./");
+    let (readable, writable) = flags.read_write();
+    if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) {
+        debug!("open {}: files cannot be created or truncated", path);
+        return None;
+    }
+
+    let mut page_caches = PAGE_CACHES.exclusive_access();
+    page_caches.retain(|_, page_cache| page_cache.strong_count() > 0);
+    let page_cache = match page_caches.get(name).and_then(Weak::upgrade) {
+        Some(page_cache) => page_cache,
+        None => {
+            let (name, data) = get_app_data_mut_by_name(name)?;
+            let page_cache = Arc::new(PageCache::new(data));
+            page_caches.insert(name, Arc::downgrade(&page_cache));
+            page_cache
+        }
+    };
+    drop(page_caches);
+    Some(Arc::new(OSInode::new(readable, writable, page_cache)))
+}
@@ -0,0 +1,22 @@
+mod inode;
+mod stdio;
+
+use alloc::sync::Arc;
+
+use crate::mm::{PageCache, UserBuffer};
+
+pub use inode::{open_file, OpenFlags};
+pub use stdio::{Stdin, Stdout};
+
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    /// Reads into `buf` and returns the number of bytes read.
+    fn read(&self, buf: UserBuffer) -> usize;
+    /// Writes from `buf` and returns the number of bytes written.
+    fn write(&self, buf: UserBuffer) -> usize;
+    /// The cached pages of a file that can be mmapped.
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        None
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{mm::UserBuffer, sbi::{console_getchar, console_putchar}, task::{current_killed, suspend_current_and_run_next}};
+
+use super::File;
+
+pub struct Stdin;
+
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// Blocks until a character arrives and returns it alone.
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+
+        let c = loop {
+            let c = console_getchar();
+            if c == usize::MAX {
+                // Give up if the thread is being killed, so it can exit.
+                if current_killed() {
+                    return 0;
+                }
+                suspend_current_and_run_next();
+                continue;
+            }
+            break c;
+        };
+        buf.buffers[0][0] = c as u8;
+        1
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        panic!("Cannot write to stdin!");
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, _buf: UserBuffer) -> usize {
+        panic!("Cannot read from stdout!");
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let len = buf.len();
+        for buffer in buf {
+            for &c in buffer.iter() {
+                console_putchar(c as usize);
+            }
+        }
+        len
+    }
+}
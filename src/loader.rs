@@ -0,0 +1,70 @@
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+use lazy_static::lazy_static;
+use log::info;
+
+global_asm!(include_str!(concat!(env!("OUT_DIR"), "/link_app.S")));
+
+extern "C" {
+    fn _num_app();
+    fn _app_names();
+}
+
+pub fn get_num_app() -> usize {
+    unsafe { (_num_app as usize as *const usize).read_volatile() }
+}
+
+/// Where the image of an app starts and how long it is.
+fn app_bounds(app_id: usize) -> (usize, usize) {
+    let num_app = get_num_app();
+    assert!(app_id < num_app, "app {} does not exist", app_id);
+
+    let app_start = unsafe { core::slice::from_raw_parts((_num_app as usize as *const usize).add(1), num_app + 1) };
+    (app_start[app_id], app_start[app_id + 1] - app_start[app_id])
+}
+
+pub fn get_app_data(app_id: usize) -> &'static [u8] {
+    let (start, len) = app_bounds(app_id);
+    unsafe { core::slice::from_raw_parts(start as *const u8, len) }
+}
+
+lazy_static! {
+    static ref APP_NAMES: Vec<&'static str> = {
+        let num_app = get_num_app();
+        let mut start = _app_names as usize as *const u8;
+        let mut names = Vec::new();
+        unsafe {
+            for _ in 0..num_app {
+                let mut end = start;
+                while end.read_volatile() != b'\0' {
+                    end = end.add(1);
+                }
+                let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
+                names.push(core::str::from_utf8(slice).unwrap());
+                start = end.add(1);
+            }
+        }
+        names
+    };
+}
+
+pub fn get_app_name(app_id: usize) -> &'static str {
+    APP_NAMES[app_id]
+}
+
+/// Returns the name as linked in, which lives forever, with the image to
+/// write in place. It is for the page cache of the file, which is the only
+/// writer. Images are linked into `.data`.
+pub fn get_app_data_mut_by_name(name: &str) -> Option<(&'static str, &'static mut [u8])> {
+    let app_id = APP_NAMES.iter().position(|&app_name| app_name == name)?;
+    let (start, len) = app_bounds(app_id);
+    Some((APP_NAMES[app_id], unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) }))
+}
+
+pub fn list_apps() {
+    info!("{} apps linked into kernel:", get_num_app());
+    for name in APP_NAMES.iter() {
+        info!("  {}", name);
+    }
+}
@@ -16,6 +16,10 @@ mod sync;
 mod trap;
 mod drivers;
 mod syscall;
+mod task;
+mod loader;
+mod fs;
+mod fdt;
 
 global_asm!(include_str!("entry.S"));
 
@@ -30,15 +34,21 @@ fn clear_bss() {
     }
 }
 
+/// OpenSBI enters with the hart id in `a0` and the device tree in `a1`,
+/// which `entry.S` leaves alone.
 #[no_mangle]
-fn kernel_main() {
+extern "C" fn kernel_main(_hartid: usize, dtb_pa: usize) {
     clear_bss();
 
     println!("Hello, YROS!");
     logger::init();
 
-    mm::init();
+    mm::init(dtb_pa);
+    mm::init_swap();
     trap::init();
+    trap::enable_timer_interrupt();
+    drivers::set_next_trigger();
 
-    loop {};
+    task::add_apps();
+    task::run_tasks();
 }
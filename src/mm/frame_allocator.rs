@@ -1,12 +1,12 @@
 use core::fmt::Debug;
 
-use alloc::vec::Vec;
+use alloc::{collections::{BTreeMap, BTreeSet}, vec, vec::Vec};
 use lazy_static::lazy_static;
 use log::{debug, info};
 
-use crate::{config::MEMORY_END, mm::address::PhysAddr, sync::UPIntrFreeCell};
+use crate::{mm::address::PhysAddr, sync::UPIntrFreeCell};
 
-use super::address::PhysPageNum;
+use super::{address::PhysPageNum, memory_layout::free_regions};
 
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
@@ -18,9 +18,23 @@ impl Debug for FrameTracker {
     }
 }
 
+/// Cloning a tracker shares the frame; it is only freed once the last
+/// tracker referring to it is dropped.
+impl Clone for FrameTracker {
+    fn clone(&self) -> Self {
+        frame_ref_inc(self.ppn);
+
+        Self {
+            ppn: self.ppn,
+        }
+    }
+}
+
 impl Drop for FrameTracker {
     fn drop(&mut self) {
-        frame_dealloc(self.ppn)
+        if frame_ref_dec(self.ppn) == 0 {
+            frame_dealloc(self.ppn)
+        }
     }
 }
 
@@ -40,76 +54,187 @@ impl FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>>;
+    /// Allocates `pages` physically contiguous frames, the first of them
+    /// aligned to `align` pages, which must be a power of two.
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
+/// Blocks of up to 2^MAX_ORDER frames, enough for a 1 GiB huge page.
+pub const MAX_ORDER: usize = 18;
+
+/// A binary buddy allocator. A free block of order `k` is `2^k` frames
+/// aligned to its size, so its buddy is found by flipping bit `k` of its
+/// first page number, and merging it back takes one lookup per order.
+pub struct BuddyFrameAllocator {
+    start: usize,
     end: usize,
-    recycled: Vec<usize>,
+    free_lists: [BTreeSet<usize>; MAX_ORDER + 1],
+    /// One bit per frame, set while it is allocated, to catch double frees.
+    allocated: Vec<u64>,
+    free: usize,
 }
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
+impl BuddyFrameAllocator {
+    /// Takes the frames in `ranges`, each as `[l, r)`. Frames in the gaps
+    /// between them are never handed out.
+    pub fn init(&mut self, ranges: &[(PhysPageNum, PhysPageNum)]) {
+        self.start = ranges.iter().map(|&(l, _)| l.0).min().unwrap();
+        self.end = ranges.iter().map(|&(_, r)| r.0).max().unwrap();
+        self.allocated = vec![0; (self.end - self.start).div_ceil(64)];
+
+        // Carve each range into the largest aligned blocks that fit.
+        for &(l, r) in ranges {
+            let mut ppn = l.0;
+            while ppn < r.0 {
+                let mut order = (ppn.trailing_zeros() as usize).min(MAX_ORDER);
+                while ppn + (1 << order) > r.0 {
+                    order -= 1;
+                }
+                self.free_lists[order].insert(ppn);
+                self.free += 1 << order;
+                ppn += 1 << order;
+            }
+        }
+    }
+
+    fn is_allocated(&self, ppn: usize) -> bool {
+        let idx = ppn - self.start;
+        self.allocated[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    fn set_allocated(&mut self, ppn: usize, allocated: bool) {
+        let idx = ppn - self.start;
+        if allocated {
+            self.allocated[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.allocated[idx / 64] &= !(1 << (idx % 64));
+        }
+    }
+
+    /// Takes a free block of `order`, splitting a larger one if need be.
+    fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let from = (order..=MAX_ORDER).find(|&k| !self.free_lists[k].is_empty())?;
+        let ppn = self.free_lists[from].pop_first().unwrap();
+        // Hand the upper halves back as we go down.
+        for k in (order..from).rev() {
+            self.free_lists[k].insert(ppn + (1 << k));
+        }
+        self.free -= 1 << order;
+        Some(ppn)
+    }
+
+    /// Returns a block of `order`, merging it with its buddy as far up as
+    /// it goes.
+    fn dealloc_order(&mut self, mut ppn: usize, mut order: usize) {
+        self.free += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(ppn);
+    }
+
+    /// How many free blocks there are of each order.
+    pub fn free_counts(&self) -> [usize; MAX_ORDER + 1] {
+        core::array::from_fn(|order| self.free_lists[order].len())
     }
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
+            start: 0,
             end: 0,
-            recycled: Vec::new(),
+            free_lists: core::array::from_fn(|_| BTreeSet::new()),
+            allocated: Vec::new(),
+            free: 0,
         }
     }
 
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
-        }
+        let ppn = self.alloc_order(0)?;
+        self.set_allocated(ppn, true);
+        Some(ppn.into())
     }
 
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>> {
-        if self.recycled.len() >= pages {
-            let v = self.recycled
-                .drain(0..pages)
-                .map(|x| x.into())
-                .collect();
-            Some(v)
-        } else if self.current + pages >= self.end {
-            None
-        } else {
-            self.current += pages;
-            let arr: Vec<usize> = (1..pages + 1).collect();
-            let v = arr.iter().map(|x| (self.current - x).into()).collect();
-            Some(v)
+    /// The block is rounded up to a power of two; the frames past `pages`
+    /// are freed again right away.
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum> {
+        assert!(pages > 0 && align.is_power_of_two());
+        let order = pages.max(align).next_power_of_two().trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            return None;
         }
+        let ppn = self.alloc_order(order)?;
+        for page in ppn..ppn + pages {
+            self.set_allocated(page, true);
+        }
+        for page in ppn + pages..ppn + (1 << order) {
+            self.dealloc_order(page, 0);
+        }
+        Some(ppn.into())
     }
 
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
-        if ppn >= self.current || self.recycled.iter().any(|&x| x == ppn) {
+        if ppn < self.start || ppn >= self.end || !self.is_allocated(ppn) {
             panic!("Frame ppn = {:#x} has not been allocated!", ppn);
         }
-        self.recycled.push(ppn);
+        self.set_allocated(ppn, false);
+        self.dealloc_order(ppn, 0);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> =
         unsafe { UPIntrFreeCell::new(FrameAllocatorImpl::new()) };
 }
 
+lazy_static! {
+    /// Reference counts of shared frames. Frames owned by a single tracker
+    /// have no entry, which stands for a count of one.
+    static ref FRAME_REF_COUNTS: UPIntrFreeCell<BTreeMap<PhysPageNum, usize>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+pub fn frame_ref_count(ppn: PhysPageNum) -> usize {
+    FRAME_REF_COUNTS
+        .exclusive_access()
+        .get(&ppn)
+        .copied()
+        .unwrap_or(1)
+}
+
+fn frame_ref_inc(ppn: PhysPageNum) {
+    *FRAME_REF_COUNTS
+        .exclusive_access()
+        .entry(ppn)
+        .or_insert(1) += 1;
+}
+
+/// Drops one reference and returns how many are left.
+fn frame_ref_dec(ppn: PhysPageNum) -> usize {
+    let mut ref_counts = FRAME_REF_COUNTS.exclusive_access();
+    match ref_counts.get_mut(&ppn) {
+        Some(count) => {
+            *count -= 1;
+            let left = *count;
+            if left == 1 {
+                ref_counts.remove(&ppn);
+            }
+            left
+        }
+        None => 0,
+    }
+}
+
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
@@ -117,15 +242,35 @@ pub fn init_frame_allocator() {
 
     info!("Initializing frame allocator.");
 
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(MEMORY_END).floor());
+    let ranges: Vec<_> = free_regions(ekernel as usize)
+        .into_iter()
+        .map(|(l, r)| (PhysAddr::from(l).floor(), PhysAddr::from(r).floor()))
+        .collect();
+    FRAME_ALLOCATOR.exclusive_access().init(&ranges);
 
-    info!("Frame range [0x{:x}000, 0x{:x}000].", PhysAddr::from(ekernel as usize).ceil().0, PhysAddr::from(MEMORY_END).floor().0);
+    for (l, r) in ranges {
+        info!("Frame range [0x{:x}000, 0x{:x}000).", l.0, r.0);
+    }
+    debug!("Free blocks by order: {:?}.", frame_free_counts());
+    frame_allocator_check();
 
     debug!("Initialized frame allocator.");
 }
 
+/// Takes a few aligned blocks, one the size of a 2 MiB huge page, and
+/// checks that freeing them merges the buddies back as they were.
+fn frame_allocator_check() {
+    for (pages, align) in [(3, 4), (512, 512)] {
+        let before = frame_free_counts();
+        let frames = frame_alloc_contiguous(pages, align).expect("no contiguous frames at boot");
+        assert_eq!(frames.len(), pages);
+        assert!(frames[0].ppn.0.is_multiple_of(align));
+        assert!(frames.windows(2).all(|pair| pair[1].ppn.0 == pair[0].ppn.0 + 1));
+        drop(frames);
+        assert_eq!(frame_free_counts(), before);
+    }
+}
+
 pub fn frame_alloc() -> Option<FrameTracker> {
     debug!("Allocating a frame.");
 
@@ -136,13 +281,24 @@ pub fn frame_alloc() -> Option<FrameTracker> {
 
 }
 
-pub fn frame_alloc_more(pages: usize) -> Option<Vec<FrameTracker>> {
-    debug!("Allocating {} frames.", pages);
+/// Allocates `pages` physically contiguous frames, the first aligned to
+/// `align` pages, e.g. for DMA buffers or huge pages.
+pub fn frame_alloc_contiguous(pages: usize, align: usize) -> Option<Vec<FrameTracker>> {
+    debug!("Allocating {} contiguous frames aligned to {} pages.", pages, align);
 
-    FRAME_ALLOCATOR
+    let first = FRAME_ALLOCATOR
         .exclusive_access()
-        .alloc_more(pages)
-        .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
+        .alloc_contiguous(pages, align)?;
+    Some((first.0..first.0 + pages).map(|ppn| FrameTracker::new(ppn.into())).collect())
+}
+
+pub fn frame_free_count() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free
+}
+
+/// How many free blocks of `2^order` frames there are, for each order.
+pub fn frame_free_counts() -> [usize; MAX_ORDER + 1] {
+    FRAME_ALLOCATOR.exclusive_access().free_counts()
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
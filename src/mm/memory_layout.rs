@@ -0,0 +1,64 @@
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use log::info;
+
+use crate::{config::PAGE_SIZE, fdt::Fdt, sync::UPIntrFreeCell};
+
+/// Physical memory as the device tree describes it, all as `[start, end)`.
+pub struct MemoryLayout {
+    pub ram: Vec<(usize, usize)>,
+    pub reserved: Vec<(usize, usize)>,
+}
+
+lazy_static! {
+    static ref MEMORY_LAYOUT: UPIntrFreeCell<MemoryLayout> =
+        unsafe { UPIntrFreeCell::new(MemoryLayout { ram: Vec::new(), reserved: Vec::new() }) };
+}
+
+/// Reads RAM and reserved regions out of the device tree at `dtb_pa`,
+/// which must still be reachable through its physical address.
+pub fn init_memory_layout(dtb_pa: usize) {
+    let fdt = unsafe { Fdt::from_addr(dtb_pa) }
+        .unwrap_or_else(|| panic!("no device tree at {:#x}", dtb_pa));
+
+    let ram = fdt.memory_regions();
+    assert!(!ram.is_empty(), "the device tree has no memory node");
+    let mut reserved = fdt.reserved_regions();
+    // Nothing reserves the blob itself, but it should outlive boot.
+    reserved.push(fdt.range());
+
+    for &(start, end) in ram.iter() {
+        info!("ram [{:#x}, {:#x})", start, end);
+    }
+    for &(start, end) in reserved.iter() {
+        info!("reserved [{:#x}, {:#x})", start, end);
+    }
+
+    *MEMORY_LAYOUT.exclusive_access() = MemoryLayout { ram, reserved };
+}
+
+/// RAM from `from` on, which is what the kernel maps and hands out.
+pub fn ram_regions(from: usize) -> Vec<(usize, usize)> {
+    MEMORY_LAYOUT
+        .exclusive_access()
+        .ram
+        .iter()
+        .map(|&(start, end)| (start.max(from).next_multiple_of(PAGE_SIZE), end & !(PAGE_SIZE - 1)))
+        .filter(|&(start, end)| start < end)
+        .collect()
+}
+
+/// RAM from `from` on without the reserved regions, in whole pages.
+pub fn free_regions(from: usize) -> Vec<(usize, usize)> {
+    let mut regions = ram_regions(from);
+    for &(start, end) in MEMORY_LAYOUT.exclusive_access().reserved.iter() {
+        let start = start & !(PAGE_SIZE - 1);
+        let end = end.next_multiple_of(PAGE_SIZE);
+        regions = regions
+            .into_iter()
+            .flat_map(|(l, r)| [(l, r.min(start)), (l.max(end), r)])
+            .filter(|&(l, r)| l < r)
+            .collect();
+    }
+    regions
+}
@@ -3,18 +3,27 @@ use core::arch::asm;
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use bitflags::bitflags;
 use lazy_static::lazy_static;
-use log::{info, warn};
+use log::{info, trace, warn};
 use riscv::register::satp;
 
-use crate::{config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE}, mm::address::StepByOne, sync::UPIntrFreeCell};
+use crate::{config::{MMIO, PAGE_SIZE, SIGRETURN_TRAMPOLINE, TRAMPOLINE, USER_STACK_SIZE, USER_STACK_TOP}, mm::address::StepByOne, sync::UPIntrFreeCell, syscall::errno::{EFAULT, ENOEXEC, ENOMEM}, task::futex_waiters_on};
 
-use super::{address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum}, frame_allocator::{frame_alloc, FrameTracker}, page_table::{PTEFlags, PageTable, PageTableEntry}, VPNRange};
+use super::{address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum}, frame_allocator::{frame_alloc, frame_ref_count, FrameTracker}, memory_layout::ram_regions, page_cache::PageCache, page_table::{level_pages, PTEFlags, PageTable, PageTableEntry, MAX_LEVEL}, swap::{is_pinned, swap_alloc, swap_free, swap_read, swap_write}, VPNRange};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MapType {
     Indentical,
     Framed,
     Linear(isize),
+    /// Framed, but a page only gets its zeroed frame when first touched.
+    Lazy,
+}
+
+impl MapType {
+    /// Whether pages own frames, tracked in `data_frame`.
+    fn is_framed(self) -> bool {
+        matches!(self, MapType::Framed | MapType::Lazy)
+    }
 }
 
 bitflags! {
@@ -27,11 +36,28 @@ bitflags! {
     }
 }
 
+/// Where the pages of a file mapping come from.
+#[derive(Clone)]
+struct FileBacking {
+    cache: Arc<PageCache>,
+    /// The page of the file mapped at the start of the area.
+    first_page: usize,
+}
+
 pub struct MapArea {
     vpn_range: VPNRange,
     data_frame: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// Frames stay shared with a forked child instead of being copied on
+    /// write, as for `MAP_SHARED`.
+    shared: bool,
+    /// Lazy pages of a file mapping are taken from its page cache instead
+    /// of being zeroed.
+    file: Option<FileBacking>,
+    /// Swap slots of pages that are swapped out, i.e. have no frame, and of
+    /// pages swapped back in, whose slot keeps a copy until they get dirty.
+    swap_slots: BTreeMap<VirtPageNum, usize>,
 }
 
 impl MapArea {
@@ -44,25 +70,78 @@ impl MapArea {
             data_frame: BTreeMap::new(),
             map_type,
             map_perm,
+            shared: false,
+            file: None,
+            swap_slots: BTreeMap::new(),
         }
     }
 
+    /// A lazy area mapping a file from its `first_page` on. Private
+    /// mappings copy a page on the first write to it.
+    pub fn new_file(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        cache: Arc<PageCache>,
+        first_page: usize,
+        shared: bool
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, MapType::Lazy, map_perm);
+        area.shared = shared;
+        area.file = Some(FileBacking { cache, first_page });
+        area
+    }
+
+    /// A lazy area whose pages are shared across fork. They come from an
+    /// anonymous page cache the children share too, so a page first
+    /// touched after fork is still the same for everyone.
+    pub fn new_shared(start_va: VirtAddr, end_va: VirtAddr, map_perm: MapPermission) -> Self {
+        Self::new_file(start_va, end_va, map_perm, Arc::new(PageCache::new_anonymous()), 0, true)
+    }
+
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frame: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            shared: another.shared,
+            file: another.file.clone(),
+            swap_slots: BTreeMap::new(),
         }
     }
 
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Splits off the pages from `at` onwards into a new area.
+    fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let skipped = at.0 - self.vpn_range.get_start().0;
+        let tail = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.get_end()),
+            data_frame: self.data_frame.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            shared: self.shared,
+            file: self.file.as_ref().map(|file| FileBacking {
+                cache: file.cache.clone(),
+                first_page: file.first_page + skipped,
+            }),
+            swap_slots: self.swap_slots.split_off(&at),
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        tail
+    }
+
+    /// Fails with `-ENOMEM`, leaving `vpn` unmapped, if there is no frame
+    /// for the page or for the page table.
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), isize> {
         let ppn: PhysPageNum = match self.map_type {
             MapType::Indentical => {
                 PhysPageNum(vpn.0)
             }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+            MapType::Framed | MapType::Lazy => {
+                let frame = match &self.file {
+                    Some(file) => file.cache.page(file.first_page + vpn.0 - self.vpn_range.get_start().0),
+                    None => frame_alloc(),
+                }.ok_or(-ENOMEM)?;
                 let frame_ppn = frame.ppn;
                 self.data_frame.insert(vpn, frame);
                 frame_ppn
@@ -72,53 +151,317 @@ impl MapArea {
                 PhysPageNum((vpn.0 as isize + pn_offset) as usize)
             }
         };
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits().into()).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        if let Err(errno) = self.map_frame(page_table, vpn, ppn) {
+            self.data_frame.remove(&vpn);
+            return Err(errno);
+        }
+        Ok(())
+    }
+
+    /// Puts `ppn` in the page table for `vpn`, unless the area is
+    /// `PROT_NONE`. A frame that is shared, e.g. with the page cache, is
+    /// mapped read-only in a private area, so it is copied before it is written.
+    fn map_frame(&self, page_table: &mut PageTable, vpn: VirtPageNum, ppn: PhysPageNum) -> Result<(), isize> {
+        if !self.is_accessible() {
+            return Ok(());
+        }
+        let mut flags = self.pte_flags();
+        if !self.shared && self.map_type.is_framed() && frame_ref_count(ppn) > 1 {
+            flags -= PTEFlags::W;
+        }
+        page_table.map(vpn, ppn, flags)
+    }
+
+    fn pte_flags(&self) -> PTEFlags {
+        PTEFlags::from_bits(self.map_perm.bits().into()).unwrap()
+    }
+
+    /// A `PROT_NONE` area keeps its frames but has nothing in the page
+    /// table, since a valid PTE without R, W or X points to the next level.
+    fn is_accessible(&self) -> bool {
+        self.map_perm.intersects(MapPermission::R | MapPermission::W | MapPermission::X)
+    }
+
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
     }
 
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frame.remove(&vpn);
+        // A lazy page that was never touched was never mapped either, and
+        // neither is a page that is swapped out.
+        let had_frame = self.data_frame.remove(&vpn).is_some();
+        if let Some(slot) = self.swap_slots.remove(&vpn) {
+            swap_free(slot);
+        }
+        if (self.map_type.is_framed() && !had_frame) || !self.is_accessible() {
+            return;
+        }
+        page_table.unmap(vpn);
+    }
+
+    /// Hands the pages of `[start_vpn, end_vpn)` that a shared file
+    /// mapping wrote to since the last time over to its page cache, and
+    /// has it write them back to the file.
+    fn write_back(&self, page_table: &mut PageTable, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let Some(file) = self.file.as_ref().filter(|_| self.shared) else {
+            return;
+        };
+        for &vpn in self.data_frame.range(start_vpn..end_vpn).map(|(vpn, _)| vpn) {
+            let dirty = page_table.translate(vpn).is_some_and(|pte| pte.is_valid() && pte.flags().contains(PTEFlags::D));
+            if dirty {
+                page_table.clear_dirty(vpn);
+                file.cache.mark_dirty(file.first_page + vpn.0 - self.vpn_range.get_start().0);
+            }
+        }
+        file.cache.write_back();
+    }
+
+    /// Whether the clock may swap this area's pages out: private user
+    /// memory the page table can see. Frames still shared with a child or
+    /// the page cache are skipped page by page.
+    fn is_swappable(&self) -> bool {
+        self.map_type.is_framed() && !self.shared && self.map_perm.contains(MapPermission::U) && self.is_accessible()
+    }
+
+    fn is_swapped_out(&self, vpn: VirtPageNum) -> bool {
+        self.swap_slots.contains_key(&vpn) && !self.data_frame.contains_key(&vpn)
+    }
+
+    /// Writes a page to swap and frees its frame. A page that is still
+    /// clean since it was swapped in is not written again. Returns `false`
+    /// if swap is full.
+    fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let ppn = self.data_frame[&vpn].ppn;
+        let dirty = page_table.translate(vpn).unwrap().flags().contains(PTEFlags::D);
+        match self.swap_slots.get(&vpn) {
+            Some(_) if !dirty => {}
+            Some(&slot) => swap_write(slot, ppn),
+            None => {
+                let Some(slot) = swap_alloc() else {
+                    return false;
+                };
+                swap_write(slot, ppn);
+                self.swap_slots.insert(vpn, slot);
+            }
         }
+        trace!("vpn {:?} is swapped out", vpn);
+        self.data_frame.remove(&vpn);
         page_table.unmap(vpn);
+        true
     }
 
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Reads a swapped out page back into a new frame. The slot is kept
+    /// as a clean copy, since a page mapped afresh has no `D` bit.
+    fn swap_in(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), isize> {
+        let frame = frame_alloc().ok_or(-ENOMEM)?;
+        swap_read(self.swap_slots[&vpn], frame.ppn);
+        trace!("vpn {:?} is swapped in", vpn);
+        page_table.map(vpn, frame.ppn, self.pte_flags())?;
+        self.data_frame.insert(vpn, frame);
+        Ok(())
+    }
+
+    /// Maps every page of the area, except for a lazy one, whose pages are
+    /// only reserved until `handle_page_fault` maps them. Either all pages
+    /// are mapped or, on failure, none are.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), isize> {
+        if self.map_type == MapType::Lazy {
+            return Ok(());
+        }
+        if self.map_type == MapType::Indentical && self.is_accessible() {
+            return self.map_identical(page_table);
+        }
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if let Err(errno) = self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return Err(errno);
+            }
         }
+        Ok(())
     }
 
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Indentical && self.is_accessible() {
+            for (vpn, _) in self.identical_pages(self.vpn_range.get_end()) {
+                page_table.unmap(vpn);
+            }
+            return;
+        }
+        self.write_back(page_table, self.vpn_range.get_start(), self.vpn_range.get_end());
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
 
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    /// Walks an identical area up to `end` in the largest pages that
+    /// alignment allows, as their first page and level.
+    fn identical_pages(&self, end: VirtPageNum) -> impl Iterator<Item = (VirtPageNum, usize)> {
+        let area_end = self.vpn_range.get_end().0;
+        let level_at = move |vpn: usize| {
+            (1..=MAX_LEVEL)
+                .rev()
+                .find(|&level| vpn.is_multiple_of(level_pages(level)) && vpn + level_pages(level) <= area_end)
+                .unwrap_or(0)
+        };
+        let start = self.vpn_range.get_start().0;
+        core::iter::successors(Some((start, level_at(start))), move |&(vpn, level)| {
+            let next = vpn + level_pages(level);
+            Some((next, level_at(next)))
+        })
+        .take_while(move |&(vpn, _)| vpn < end.0)
+        .map(|(vpn, level)| (VirtPageNum(vpn), level))
+    }
+
+    /// Maps an identical area with huge pages wherever they fit, which
+    /// saves page table frames and TLB entries over a large range.
+    fn map_identical(&mut self, page_table: &mut PageTable) -> Result<(), isize> {
+        let flags = self.pte_flags();
+        for (vpn, level) in self.identical_pages(self.vpn_range.get_end()) {
+            let ppn = PhysPageNum(vpn.0);
+            let result = if level == 0 {
+                page_table.map(vpn, ppn, flags)
+            } else {
+                page_table.map_huge(vpn, ppn, flags, level)
+            };
+            if let Err(errno) = result {
+                for (mapped, _) in self.identical_pages(vpn) {
+                    page_table.unmap(mapped);
+                }
+                return Err(errno);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the permissions of every page in place. Pages still shared
+    /// copy-on-write stay read-only, so the next store copies them. Making
+    /// a `PROT_NONE` area accessible again may need page table frames; if
+    /// there are none, the area stays `PROT_NONE`.
+    fn protect(&mut self, page_table: &mut PageTable, map_perm: MapPermission) -> Result<(), isize> {
+        let was_accessible = self.is_accessible();
+        let old_perm = self.map_perm;
+        self.map_perm = map_perm;
+        let is_accessible = self.is_accessible();
+
+        let pages: Vec<(VirtPageNum, PhysPageNum)> = if self.map_type.is_framed() {
+            self.data_frame.iter().map(|(&vpn, frame)| (vpn, frame.ppn)).collect()
+        } else {
+            self.vpn_range.into_iter().map(|vpn| (vpn, page_table.translate(vpn).unwrap().ppn())).collect()
+        };
+        for (idx, &(vpn, ppn)) in pages.iter().enumerate() {
+            let mut flags = self.pte_flags();
+            if !self.shared && self.map_type.is_framed() && frame_ref_count(ppn) > 1 {
+                flags -= PTEFlags::W;
+            }
+            match (was_accessible, is_accessible) {
+                (true, true) => page_table.remap(vpn, ppn, flags),
+                (true, false) => {
+                    // The D bit goes with the PTE, so a copy in swap can
+                    // no longer be told clean.
+                    if let Some(slot) = self.swap_slots.remove(&vpn) {
+                        swap_free(slot);
+                    }
+                    page_table.unmap(vpn)
+                }
+                (false, true) => {
+                    if let Err(errno) = page_table.map(vpn, ppn, flags) {
+                        for &(mapped, _) in &pages[..idx] {
+                            page_table.unmap(mapped);
+                        }
+                        self.map_perm = old_perm;
+                        return Err(errno);
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Grows the area upwards so that it ends at `new_end`. On failure it
+    /// is left as it was.
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) -> Result<(), isize> {
+        let old_end = self.vpn_range.get_end();
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+        if self.map_type != MapType::Lazy {
+            for vpn in VPNRange::new(old_end, new_end) {
+                if let Err(errno) = self.map_one(page_table, vpn) {
+                    self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+                    self.shrink_to(page_table, old_end);
+                    return Err(errno);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Shrinks the area from above so that it ends at `new_end`.
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+
+    /// Gives this area a private, writable copy of a page shared by fork.
+    fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), isize> {
+        let frame = self.data_frame.get(&vpn).unwrap();
+        let old_ppn = frame.ppn;
+
+        if frame_ref_count(old_ppn) == 1 {
+            trace!("vpn {:?} is no longer shared, reuse ppn {:?}", vpn, old_ppn);
+            page_table.remap(vpn, old_ppn, self.pte_flags());
+            return Ok(());
+        }
+
+        let new_frame = frame_alloc().ok_or(-ENOMEM)?;
+        let new_ppn = new_frame.ppn;
+        new_ppn.get_byte_array().copy_from_slice(old_ppn.get_byte_array());
+        trace!("vpn {:?} is copied from ppn {:?} to ppn {:?}", vpn, old_ppn, new_ppn);
+
+        self.data_frame.insert(vpn, new_frame);
+        page_table.remap(vpn, new_ppn, self.pte_flags());
+        Ok(())
+    }
+
+    /// Copies `data` into the area, starting `offset` bytes into its first page.
+    pub fn copy_data(&mut self, page_table: &mut PageTable, offset: usize, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
+        assert!(offset < PAGE_SIZE);
 
         let mut start: usize = 0;
+        let mut page_offset = offset;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
 
         loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
+            let src = &data[start..len.min(start + PAGE_SIZE - page_offset)];
             let dst = &mut page_table
                 .translate(current_vpn)
                 .unwrap()
                 .ppn()
-                .get_byte_array()[..src.len()];
+                .get_byte_array()[page_offset..page_offset + src.len()];
             dst.copy_from_slice(src);
-            start += PAGE_SIZE;
+            start += src.len();
             if start >= len {
                 break;
             }
+            page_offset = 0;
             current_vpn.step();
         }
     }
 }
 
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        for &slot in self.swap_slots.values() {
+            swap_free(slot);
+        }
+    }
+}
+
 extern "C" {
     fn stext();
     fn etext();
@@ -130,6 +473,7 @@ extern "C" {
     fn ebss();
     fn ekernel();
     fn strampoline();
+    fn ssigreturn();
 }
 
 lazy_static! {
@@ -141,33 +485,71 @@ pub fn kernel_satp() -> usize {
     KERNEL_SPACE.exclusive_access().satp()
 }
 
+/// A `PT_LOAD` segment of an ELF image, checked but not mapped yet.
+struct ElfSegment {
+    start: usize,
+    end: usize,
+    map_perm: MapPermission,
+    file_start: usize,
+    file_end: usize,
+}
+
+/// What the loader learned about a program, for the initial user stack.
+pub struct ElfInfo {
+    pub entry: usize,
+    pub user_sp: usize,
+    /// Where the program headers are mapped, for `AT_PHDR`.
+    pub phdr: usize,
+    pub phent: usize,
+    pub phnum: usize,
+}
+
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// The heap starts right after the highest ELF segment, in a lazy area
+    /// that `brk` grows and shrinks. Both are 0 outside of user spaces.
+    heap_bottom: usize,
+    brk: usize,
+}
+
+/// A space replaced by `exec` goes without `recycle_data_pages`.
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        self.write_back_range(VirtPageNum(0), VirtPageNum(usize::MAX));
+    }
 }
 
 impl MemorySet {
-    pub fn new() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    pub fn new() -> Result<Self, isize> {
+        Ok(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            heap_bottom: 0,
+            brk: 0,
+        })
     }
 
     pub fn satp(&self) -> usize {
         self.page_table.satp()
     }
 
-    pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    pub fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) -> Result<(), isize> {
+        self.push_with_offset(map_area, 0, data)
+    }
+
+    /// Fails with `-ENOMEM`, adding nothing, if the area cannot be mapped.
+    pub fn push_with_offset(&mut self, mut map_area: MapArea, offset: usize, data: Option<&[u8]>) -> Result<(), isize> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.copy_data(&mut self.page_table, offset, data);
         }
         self.areas.push(map_area);
+        Ok(())
     }
 
-    pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, map_perm: MapPermission) {
-        self.push(MapArea::new(start_va, end_va, MapType::Framed, map_perm), None);
+    pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, map_perm: MapPermission) -> Result<(), isize> {
+        self.push(MapArea::new(start_va, end_va, MapType::Framed, map_perm), None)
     }
 
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
@@ -177,6 +559,320 @@ impl MemorySet {
         }
     }
 
+    /// Duplicates a user address space for fork. Writable user pages are
+    /// shared read-only by both sides and copied on the first store; pages
+    /// the kernel writes directly, like the trap context, are copied now.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Result<Self, isize> {
+        let mut memory_set = Self::new()?;
+
+        memory_set.map_trampoline()?;
+        memory_set.map_sigreturn_trampoline()?;
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+
+        for area in user_space.areas.iter() {
+            let mut new_area = MapArea::from_another(area);
+
+            if !area.map_type.is_framed() {
+                new_area.map(&mut memory_set.page_table)?;
+            } else if !area.map_perm.contains(MapPermission::U) {
+                new_area.map(&mut memory_set.page_table)?;
+                for vpn in area.vpn_range {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn.get_byte_array().copy_from_slice(src_ppn.get_byte_array());
+                }
+            } else if area.shared {
+                for (&vpn, frame) in area.data_frame.iter() {
+                    new_area.data_frame.insert(vpn, frame.clone());
+                    if area.is_accessible() {
+                        memory_set.page_table.map(vpn, frame.ppn, area.pte_flags())?;
+                    }
+                }
+            } else {
+                let shared_flags = area.pte_flags() - PTEFlags::W;
+                for (&vpn, frame) in area.data_frame.iter() {
+                    new_area.data_frame.insert(vpn, frame.clone());
+                    if area.is_accessible() {
+                        memory_set.page_table.map(vpn, frame.ppn, shared_flags)?;
+                        user_space.page_table.remap(vpn, frame.ppn, shared_flags);
+                    }
+                }
+                // The child reads its own copy of swapped out pages.
+                for (&vpn, &slot) in area.swap_slots.iter() {
+                    if area.data_frame.contains_key(&vpn) {
+                        continue;
+                    }
+                    let frame = frame_alloc().ok_or(-ENOMEM)?;
+                    swap_read(slot, frame.ppn);
+                    let ppn = frame.ppn;
+                    new_area.data_frame.insert(vpn, frame);
+                    if area.is_accessible() {
+                        memory_set.page_table.map(vpn, ppn, area.pte_flags())?;
+                    }
+                }
+            }
+
+            memory_set.areas.push(new_area);
+        }
+
+        Ok(memory_set)
+    }
+
+    /// Resolves a user page fault that the address space can legally
+    /// satisfy. Returns `Ok(false)` if the access is a genuine fault, and
+    /// `-ENOMEM` if it is not but there is no memory to resolve it with.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, is_write: bool) -> Result<bool, isize> {
+        let vpn = va.floor();
+        let Some(area) = self.areas.iter_mut().find(|area| area.contains(vpn)) else {
+            return Ok(false);
+        };
+
+        let mapped = self.page_table.translate(vpn).is_some_and(|pte| pte.is_valid());
+        let allowed = area.is_accessible() && (!is_write || area.map_perm.contains(MapPermission::W));
+        if !mapped && area.is_swapped_out(vpn) {
+            if !allowed {
+                return Ok(false);
+            }
+            area.swap_in(&mut self.page_table, vpn)?;
+            return Ok(true);
+        }
+        if !mapped && area.map_type == MapType::Lazy {
+            if !allowed {
+                return Ok(false);
+            }
+            trace!("vpn {:?} is touched for the first time", vpn);
+            area.map_one(&mut self.page_table, vpn)?;
+            // A write to a private file page goes on to copy it.
+            if !is_write || self.page_table.translate(vpn).is_some_and(|pte| pte.writable()) {
+                return Ok(true);
+            }
+        }
+
+        if is_write && area.map_type.is_framed() && !area.shared && area.map_perm.contains(MapPermission::W) {
+            match self.page_table.translate(vpn) {
+                Some(pte) if pte.is_valid() && !pte.writable() => {
+                    area.copy_on_write(&mut self.page_table, vpn)?;
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Whether any area has a page in `[start_vpn, end_vpn)`.
+    pub fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end())
+    }
+
+    /// Finds room for `pages` pages, as high up as possible below the user
+    /// stack and above the heap, leaving a guard page under the stack.
+    pub fn find_free_area(&self, pages: usize) -> Option<VirtPageNum> {
+        let bottom = VirtAddr::from(self.brk).ceil().0.max(1);
+        let mut top = VirtAddr::from(USER_STACK_TOP - USER_STACK_SIZE).floor().0 - 1;
+
+        let mut ranges: Vec<(usize, usize)> = self.areas
+            .iter()
+            .map(|area| (area.vpn_range.get_start().0, area.vpn_range.get_end().0))
+            .collect();
+        ranges.sort_unstable();
+        for &(start, end) in ranges.iter().rev() {
+            if end <= top && top >= end.max(bottom) + pages {
+                return Some(VirtPageNum(top - pages));
+            }
+            top = top.min(start);
+            if top <= bottom {
+                return None;
+            }
+        }
+        (top >= bottom + pages).then(|| VirtPageNum(top - pages))
+    }
+
+    /// Takes whatever lies in `[start_vpn, end_vpn)` out of the set,
+    /// splitting areas that straddle either end; the rest stays in place.
+    fn take_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> Vec<MapArea> {
+        let mut taken = Vec::new();
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let (area_start, area_end) = (self.areas[idx].vpn_range.get_start(), self.areas[idx].vpn_range.get_end());
+            if area_end <= start_vpn || end_vpn <= area_start || area_start == area_end {
+                idx += 1;
+                continue;
+            }
+
+            let mut area = self.areas.swap_remove(idx);
+            if end_vpn < area_end {
+                self.areas.push(area.split_off(end_vpn));
+            }
+            if area_start < start_vpn {
+                taken.push(area.split_off(start_vpn));
+                self.areas.push(area);
+            } else {
+                taken.push(area);
+            }
+        }
+        taken
+    }
+
+    /// Unmaps every page in `[start_vpn, end_vpn)`, whichever areas they
+    /// belong to. Holes in the range are fine.
+    pub fn unmap_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        for mut area in self.take_range(start_vpn, end_vpn) {
+            area.unmap(&mut self.page_table);
+        }
+    }
+
+    /// Whether every page in `[start_vpn, end_vpn)` belongs to some area.
+    pub fn covers(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        let covered: usize = self.areas
+            .iter()
+            .map(|area| {
+                let start = area.vpn_range.get_start().max(start_vpn).0;
+                let end = area.vpn_range.get_end().min(end_vpn).0;
+                end.saturating_sub(start)
+            })
+            .sum();
+        covered == end_vpn.0 - start_vpn.0
+    }
+
+    /// Changes the permissions of `[start_vpn, end_vpn)`, which must be
+    /// fully mapped. Fails with `-ENOMEM`, changing nothing, if it is not,
+    /// and also if an area runs out of memory, which then keeps its old
+    /// permissions while the others are changed, as on Linux.
+    pub fn protect_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum, map_perm: MapPermission) -> Result<(), isize> {
+        if !self.covers(start_vpn, end_vpn) {
+            return Err(-ENOMEM);
+        }
+
+        let mut result = Ok(());
+        for mut area in self.take_range(start_vpn, end_vpn) {
+            if let Err(errno) = area.protect(&mut self.page_table, map_perm) {
+                result = Err(errno);
+            }
+            self.areas.push(area);
+        }
+        result
+    }
+
+    /// Moves the end of the heap to `new_brk`, page-granularly underneath,
+    /// and returns the resulting break. Like Linux, a request that cannot be
+    /// satisfied leaves the break where it was, and 0 just queries it.
+    pub fn set_brk(&mut self, new_brk: usize) -> usize {
+        if new_brk < self.heap_bottom || new_brk > USER_STACK_TOP - USER_STACK_SIZE {
+            return self.brk;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if new_end > old_end && self.overlaps(old_end, new_end) {
+            return self.brk;
+        }
+        if new_end != old_end {
+            // munmap may have cut into the heap, which then stays as it is.
+            let Some(area) = self.areas
+                .iter_mut()
+                .find(|area| area.vpn_range.get_start() == heap_start && area.vpn_range.get_end() == old_end)
+            else {
+                return self.brk;
+            };
+            if new_end > old_end {
+                if area.append_to(&mut self.page_table, new_end).is_err() {
+                    return self.brk;
+                }
+            } else {
+                area.shrink_to(&mut self.page_table, new_end);
+            }
+        }
+
+        trace!("brk moved from {:#x} to {:#x}", self.brk, new_brk);
+        self.brk = new_brk;
+        self.brk
+    }
+
+    /// Maps the lazy pages of `[start_va, end_va)` right away, for when the
+    /// kernel fills an address space that is not the current one.
+    pub fn populate(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> Result<(), isize> {
+        for vpn in VPNRange::new(start_va.floor(), end_va.ceil()) {
+            if self.page_table.translate(vpn).is_some_and(|pte| pte.is_valid()) {
+                continue;
+            }
+            let Some(area) = self.areas.iter_mut().find(|area| area.contains(vpn)) else {
+                continue;
+            };
+            if area.is_swapped_out(vpn) {
+                area.swap_in(&mut self.page_table, vpn)?;
+            } else if area.map_type == MapType::Lazy && !area.data_frame.contains_key(&vpn) {
+                area.map_one(&mut self.page_table, vpn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at `va` in this address space, which need not be the
+    /// current one, so no page fault is taken on its behalf: lazy pages are
+    /// populated first, and anything not writable fails with `-EFAULT`.
+    pub fn write_bytes(&mut self, va: usize, data: &[u8]) -> Result<(), isize> {
+        let end = va.checked_add(data.len()).ok_or(-EFAULT)?;
+        self.populate(VirtAddr::from(va), VirtAddr::from(end))?;
+
+        let mut written = 0;
+        while written < data.len() {
+            let va = VirtAddr::from(va + written);
+            let ppn = self.page_table
+                .translate(va.floor())
+                .filter(|pte| pte.is_valid() && pte.writable())
+                .ok_or(-EFAULT)?
+                .ppn();
+            let offset = va.page_offset();
+            let len = (PAGE_SIZE - offset).min(data.len() - written);
+            ppn.get_byte_array()[offset..offset + len].copy_from_slice(&data[written..written + len]);
+            self.page_table.mark_used(va.floor(), true);
+            written += len;
+        }
+        Ok(())
+    }
+
+    /// Moves the clock hand over the swappable pages of this space, from
+    /// `from` on. A page used since the hand last passed it has its `A` bit
+    /// cleared and gets a second chance; one that was not is swapped out,
+    /// until `wanted` pages are. Returns the page the hand stopped at, or
+    /// `None` if it went past the last one.
+    pub fn sweep(&mut self, from: VirtPageNum, wanted: &mut usize) -> Option<VirtPageNum> {
+        let mut pages: Vec<(VirtPageNum, usize)> = self.areas
+            .iter()
+            .enumerate()
+            .filter(|(_, area)| area.is_swappable())
+            .flat_map(|(idx, area)| {
+                area.data_frame
+                    .range(from..)
+                    .filter(|(_, frame)| {
+                        frame_ref_count(frame.ppn) == 1 && !is_pinned(frame.ppn) && !futex_waiters_on(frame.ppn)
+                    })
+                    .map(move |(&vpn, _)| (vpn, idx))
+            })
+            .collect();
+        pages.sort_unstable();
+
+        for (vpn, idx) in pages {
+            if *wanted == 0 {
+                return Some(vpn);
+            }
+            if self.page_table.translate(vpn).unwrap().flags().contains(PTEFlags::A) {
+                self.page_table.clear_accessed(vpn);
+                continue;
+            }
+            if !self.areas[idx].swap_out(&mut self.page_table, vpn) {
+                trace!("swap is full");
+                *wanted = 0;
+                return Some(vpn);
+            }
+            *wanted -= 1;
+        }
+        None
+    }
+
     pub fn activate(&self) {
         let satp = self.page_table.satp();
         warn!("set satp 0x{:#x}", satp);
@@ -190,11 +886,32 @@ impl MemorySet {
         self.page_table.translate(vpn)
     }
 
+    /// User pages whose frames only this space holds, i.e. what killing
+    /// it would free. Pages shared copy-on-write or with a page cache do
+    /// not count.
+    pub fn resident_pages(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .map(|area| area.data_frame.values().filter(|frame| frame_ref_count(frame.ppn) == 1).count())
+            .sum()
+    }
+
+    /// Writes what shared file mappings in `[start_vpn, end_vpn)` wrote
+    /// back to their files.
+    pub fn write_back_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        for area in self.areas.iter() {
+            area.write_back(&mut self.page_table, start_vpn, end_vpn);
+        }
+    }
+
+    /// Frees the user pages, after writing shared file mappings back.
     pub fn recycle_data_pages(&mut self) {
+        self.write_back_range(VirtPageNum(0), VirtPageNum(usize::MAX));
         self.areas.clear();
     }
 
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Result<(), isize> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
@@ -202,11 +919,21 @@ impl MemorySet {
         )
     }
 
+    /// The `rt_sigreturn` stub signal handlers return to, shared by all
+    /// user spaces like the trampoline, but user accessible.
+    fn map_sigreturn_trampoline(&mut self) -> Result<(), isize> {
+        self.page_table.map(
+            VirtAddr::from(SIGRETURN_TRAMPOLINE).into(),
+            PhysAddr::from(ssigreturn as usize).into(),
+            PTEFlags::R | PTEFlags::X | PTEFlags::U
+        )
+    }
+
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new();
+        let mut memory_set = Self::new().unwrap();
 
         info!("kernel map trampoline");
-        memory_set.map_trampoline();
+        memory_set.map_trampoline().unwrap();
 
         info!("kernel map .text [0x{:#x}, 0x{:#x}]", stext as usize, etext as usize);
         memory_set.push(
@@ -217,7 +944,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::X
             ),
             None
-        );
+        ).unwrap();
 
         info!("kernel map .rodata [0x{:#x}, 0x{:#x}]", srodata as usize, erodata as usize);
         memory_set.push(
@@ -228,7 +955,7 @@ impl MemorySet {
                 MapPermission::R
             ),
             None
-        );
+        ).unwrap();
         
         info!("kernel map .data [0x{:#x}, 0x{:#x}]", sdata as usize, edata as usize);
         memory_set.push(
@@ -239,7 +966,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W
             ),
             None
-        );
+        ).unwrap();
 
         info!("kernel map .bss [0x{:#x}, 0x{:#x}]", sbss_with_stack as usize, ebss as usize);
         memory_set.push(
@@ -250,19 +977,200 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W
             ),
             None
-        );
+        ).unwrap();
+
+        for (start, end) in ram_regions(ekernel as usize) {
+            info!("kernel map physics memory [{:#x}, {:#x})", start, end);
+            memory_set.push(
+                MapArea::new(
+                    start.into(),
+                    end.into(),
+                    MapType::Indentical,
+                    MapPermission::R | MapPermission::W
+                ),
+                None
+            ).unwrap();
+        }
+
+        for &(base, size) in MMIO {
+            info!("kernel map mmio [{:#x}, {:#x})", base, base + size);
+            memory_set.push(
+                MapArea::new(
+                    base.into(),
+                    (base + size).into(),
+                    MapType::Indentical,
+                    MapPermission::R | MapPermission::W
+                ),
+                None
+            ).unwrap();
+        }
 
-        info!("kernel map physics memory [0x{:#x}, 0x{:#x}]", ekernel as usize, MEMORY_END);
+        memory_set
+    }
+
+    /// Builds a user address space from an ELF image. Fails with
+    /// `-ENOEXEC` if the image is not a well-formed 64-bit RISC-V
+    /// executable, and with `-ENOMEM` if it does not fit in memory.
+    /// With the `page_cache` of its file, read-only segments are mapped
+    /// straight from the cache instead of copied.
+    pub fn from_elf(elf_data: &[u8], page_cache: Option<&Arc<PageCache>>) -> Result<(Self, ElfInfo), isize> {
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| -ENOEXEC)?;
+        let elf_header = elf.header;
+        if elf_header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || elf_header.pt2.machine().as_machine() != xmas_elf::header::Machine::RISC_V
+        {
+            warn!("elf is not a 64-bit RISC-V executable!");
+            return Err(-ENOEXEC);
+        }
+
+        let mut memory_set = Self::new()?;
+
+        memory_set.map_trampoline()?;
+        memory_set.map_sigreturn_trampoline()?;
+
+        let ph_offset = elf_header.pt2.ph_offset() as usize;
+        let mut phdr = None;
+        let mut segments: Vec<ElfSegment> = Vec::new();
+
+        for ph in elf.program_iter() {
+            match ph.get_type() {
+                Ok(xmas_elf::program::Type::Phdr) => {
+                    phdr = Some(ph.virtual_addr() as usize);
+                    continue;
+                }
+                Ok(xmas_elf::program::Type::Load) if ph.mem_size() > 0 => {}
+                _ => continue,
+            }
+
+            // Segments go below the user stack, in ascending order as the
+            // ELF spec has them, and without overlapping.
+            let start = ph.virtual_addr() as usize;
+            let end = match start.checked_add(ph.mem_size() as usize) {
+                Some(end) if end <= USER_STACK_TOP - USER_STACK_SIZE => end,
+                _ => {
+                    warn!("elf segment at {:#x} is out of the user space!", start);
+                    return Err(-ENOEXEC);
+                }
+            };
+            if segments.last().is_some_and(|last| start < last.end) {
+                warn!("elf segment [{:#x}, {:#x}) overlaps another one!", start, end);
+                return Err(-ENOEXEC);
+            }
+
+            let mut map_perm = MapPermission::U;
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() {
+                map_perm |= MapPermission::R;
+            }
+            if ph_flags.is_write() {
+                map_perm |= MapPermission::W;
+            }
+            if ph_flags.is_execute() {
+                map_perm |= MapPermission::X;
+            }
+
+            let file_start = ph.offset() as usize;
+            let file_end = file_start.saturating_add(ph.file_size() as usize);
+            if file_end > elf_data.len() || ph.file_size() > ph.mem_size() {
+                warn!("elf segment [{:#x}, {:#x}) is out of the image!", file_start, file_end);
+                return Err(-ENOEXEC);
+            }
+
+            // The program headers live in the segment that maps file offset 0.
+            if phdr.is_none() && (file_start..file_end).contains(&ph_offset) {
+                phdr = Some(start + ph_offset - file_start);
+            }
+
+            segments.push(ElfSegment { start, end, map_perm, file_start, file_end });
+        }
+
+        let mut max_end_va = VirtAddr::from(0);
+        for (idx, segment) in segments.iter().enumerate() {
+            let start_va = VirtAddr::from(segment.start);
+            let end_va = VirtAddr::from(segment.end);
+            let last_vpn = VirtAddr::from(segment.end - 1).floor();
+            max_end_va = max_end_va.max(end_va);
+            info!("user map segment [{:#x}, {:#x}) {:?}", start_va.0, end_va.0, segment.map_perm);
+
+            // Linkers let neighbouring segments share the page where one ends
+            // and the next starts. It is mapped once, by the first of them,
+            // with the permissions of both.
+            let shares_first = idx > 0 && VirtAddr::from(segments[idx - 1].end - 1).floor() == start_va.floor();
+            let shares_last = segments.get(idx + 1).is_some_and(|next| VirtAddr::from(next.start).floor() == last_vpn);
+            let mut data = &elf.input[segment.file_start..segment.file_end];
+            let mut area_start_va = start_va;
+            if shares_first {
+                let vpn = start_va.floor();
+                let old_perm = memory_set.areas.iter().find(|area| area.contains(vpn)).unwrap().map_perm;
+                memory_set.protect_range(vpn, VirtPageNum(vpn.0 + 1), old_perm | segment.map_perm)?;
+
+                let offset = start_va.page_offset();
+                let len = data.len().min(PAGE_SIZE - offset);
+                let ppn = memory_set.page_table.translate(vpn).unwrap().ppn();
+                ppn.get_byte_array()[offset..offset + len].copy_from_slice(&data[..len]);
+                data = &data[len..];
+                if vpn == last_vpn {
+                    continue;
+                }
+                area_start_va = VirtAddr::from(VirtPageNum(vpn.0 + 1));
+            }
+
+            // A page shared with a neighbour is written to, so it cannot
+            // come from the page cache.
+            let cacheable = !shares_first
+                && !shares_last
+                && !segment.map_perm.contains(MapPermission::W)
+                && segment.file_start % PAGE_SIZE == start_va.page_offset()
+                && segment.file_end - segment.file_start == segment.end - segment.start;
+            if let Some(page_cache) = page_cache.filter(|_| cacheable) {
+                memory_set.push(
+                    MapArea::new_file(start_va, end_va, segment.map_perm, page_cache.clone(), segment.file_start / PAGE_SIZE, false),
+                    None
+                )?;
+                continue;
+            }
+            memory_set.push_with_offset(
+                MapArea::new(area_start_va, end_va, MapType::Framed, segment.map_perm),
+                area_start_va.page_offset(),
+                Some(data)
+            )?;
+        }
+
+        // Empty until brk grows it.
+        let heap_bottom: usize = VirtAddr::from(max_end_va.ceil()).into();
+        info!("user heap starts at {:#x}", heap_bottom);
         memory_set.push(
             MapArea::new(
-                (ekernel as usize).into(),
-                MEMORY_END.into(),
-                MapType::Indentical,
-                MapPermission::R | MapPermission::W
+                VirtAddr::from(heap_bottom),
+                VirtAddr::from(heap_bottom),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U
             ),
             None
-        );
+        )?;
+        memory_set.heap_bottom = heap_bottom;
+        memory_set.brk = heap_bottom;
 
-        memory_set
+        let user_stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+        info!("user map stack [{:#x}, {:#x})", user_stack_bottom, USER_STACK_TOP);
+        memory_set.push(
+            MapArea::new(
+                VirtAddr::from(user_stack_bottom),
+                VirtAddr::from(USER_STACK_TOP),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U
+            ),
+            None
+        )?;
+
+        let elf_info = ElfInfo {
+            entry: elf_header.pt2.entry_point() as usize,
+            user_sp: USER_STACK_TOP,
+            phdr: phdr.unwrap_or(0),
+            phent: elf_header.pt2.ph_entry_size() as usize,
+            phnum: elf_header.pt2.ph_count() as usize,
+        };
+
+        Ok((memory_set, elf_info))
     }
 }
@@ -2,13 +2,23 @@ mod address;
 mod page_table;
 mod heap_allocator;
 mod frame_allocator;
+mod memory_layout;
 mod memory_set;
+mod page_cache;
+mod swap;
+mod user_buffer;
 
-pub use address::{VPNRange, PPNRange};
-pub use memory_set::KERNEL_SPACE;
+pub use address::{VPNRange, PPNRange, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+pub use memory_set::{kernel_satp, ElfInfo, MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE};
+pub use page_cache::PageCache;
+pub use swap::{frames_wanted, init_swap, FramePin};
+pub use user_buffer::{pin_user_addr, read_user_cstr, translate_user_addr, UserBuffer, UserPtr};
 
-pub fn init() {
+/// `dtb_pa` is the device tree the firmware passed, still reachable
+/// through its physical address.
+pub fn init(dtb_pa: usize) {
     heap_allocator::init_heap();
+    memory_layout::init_memory_layout(dtb_pa);
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.exclusive_access().activate();
 }
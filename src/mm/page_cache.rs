@@ -0,0 +1,130 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use log::trace;
+
+use crate::{config::PAGE_SIZE, sync::UPIntrFreeCell};
+
+use super::frame_allocator::{frame_alloc, FrameTracker};
+
+/// The pages of one file, read in on first use and kept for as long as the
+/// cache lives. Mappings hold clones of the trackers, so a page is shared
+/// by every mapping of the file and private ones copy it on write.
+pub struct PageCache {
+    inner: UPIntrFreeCell<PageCacheInner>,
+}
+
+struct PageCacheInner {
+    /// The file, written in place since files keep their size. An
+    /// anonymous cache has no file at all, so nothing is written back.
+    data: &'static mut [u8],
+    pages: BTreeMap<usize, FrameTracker>,
+    /// Pages written through a shared mapping since their last write back.
+    dirty: BTreeSet<usize>,
+}
+
+impl PageCache {
+    pub fn new(data: &'static mut [u8]) -> Self {
+        Self {
+            inner: unsafe {
+                UPIntrFreeCell::new(PageCacheInner {
+                    data,
+                    pages: BTreeMap::new(),
+                    dirty: BTreeSet::new(),
+                })
+            },
+        }
+    }
+
+    /// Zero-filled pages that belong to no file, for shared anonymous
+    /// memory.
+    pub fn new_anonymous() -> Self {
+        Self::new(&mut [])
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.inner.exclusive_access().data.len()
+    }
+
+    /// Returns the `index`-th page of the file. Whatever lies past the end
+    /// of the file reads as zeros. Returns `None` if there is no frame to
+    /// read it into.
+    pub fn page(&self, index: usize) -> Option<FrameTracker> {
+        let mut inner = self.inner.exclusive_access();
+        if let Some(frame) = inner.pages.get(&index) {
+            return Some(frame.clone());
+        }
+
+        let frame = frame_alloc()?;
+        let (start, end) = page_range(inner.data.len(), index);
+        frame.ppn.get_byte_array()[..end - start].copy_from_slice(&inner.data[start..end]);
+        trace!("page {} of a file is cached in ppn {:?}", index, frame.ppn);
+
+        inner.pages.insert(index, frame.clone());
+        Some(frame)
+    }
+
+    /// Reads the file from `offset` on, through cached pages where there
+    /// are any, so what shared mappings wrote is seen. Returns how many
+    /// bytes were read, which is short at the end of the file.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.exclusive_access();
+        let end = offset.saturating_add(buf.len()).min(inner.data.len());
+        let mut pos = offset;
+        while pos < end {
+            let (index, page_offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let src = match inner.pages.get(&index) {
+                Some(frame) => &frame.ppn.get_byte_array()[page_offset..page_offset + len],
+                None => &inner.data[pos..pos + len],
+            };
+            buf[pos - offset..pos - offset + len].copy_from_slice(src);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// Writes the file from `offset` on, and any cached page it touches.
+    /// Files cannot grow, so the write is short at the end of the file.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let inner = &mut *self.inner.exclusive_access();
+        let end = offset.saturating_add(buf.len()).min(inner.data.len());
+        let mut pos = offset;
+        while pos < end {
+            let (index, page_offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let src = &buf[pos - offset..pos - offset + len];
+            if let Some(frame) = inner.pages.get(&index) {
+                frame.ppn.get_byte_array()[page_offset..page_offset + len].copy_from_slice(src);
+            }
+            inner.data[pos..pos + len].copy_from_slice(src);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// Notes that a mapping wrote to the cached `index`-th page.
+    pub fn mark_dirty(&self, index: usize) {
+        let mut inner = self.inner.exclusive_access();
+        if inner.pages.contains_key(&index) {
+            inner.dirty.insert(index);
+        }
+    }
+
+    /// Copies every dirty page into the file. What lies past its end is
+    /// dropped.
+    pub fn write_back(&self) {
+        let inner = &mut *self.inner.exclusive_access();
+        for index in core::mem::take(&mut inner.dirty) {
+            let (start, end) = page_range(inner.data.len(), index);
+            inner.data[start..end].copy_from_slice(&inner.pages[&index].ppn.get_byte_array()[..end - start]);
+            trace!("page {} of a file is written back", index);
+        }
+    }
+}
+
+/// The bytes of a file of `len` bytes that its `index`-th page holds.
+fn page_range(len: usize, index: usize) -> (usize, usize) {
+    let start = index.checked_mul(PAGE_SIZE).map_or(len, |start| start.min(len));
+    (start, (start + PAGE_SIZE).min(len))
+}
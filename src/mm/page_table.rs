@@ -3,7 +3,7 @@ use alloc::vec;
 use bitflags::bitflags;
 use log::{debug, trace, warn};
 
-use crate::config::PPN_WIDTH;
+use crate::{config::PPN_WIDTH, syscall::errno::ENOMEM};
 
 use super::{address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum}, frame_allocator::{frame_alloc, FrameTracker}};
 
@@ -60,6 +60,21 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    /// A valid entry with any of R, W or X maps memory; without them it
+    /// points to the next level.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && self.flags().intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+/// Levels as Sv39 counts them: a leaf at level 1 maps a 2 MiB megapage,
+/// one at level 2 a 1 GiB gigapage.
+pub const MAX_LEVEL: usize = 2;
+
+/// How many 4 KiB pages a leaf at `level` covers.
+pub const fn level_pages(level: usize) -> usize {
+    1 << (9 * level)
 }
 
 pub struct PageTable {
@@ -68,13 +83,13 @@ pub struct PageTable {
 }
 
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
+    pub fn new() -> Result<Self, isize> {
+        let frame = frame_alloc().ok_or(-ENOMEM)?;
 
-        Self {
+        Ok(Self {
             root_ppn: frame.ppn,
             frames: vec![frame],
-        }
+        })
     }
     
     pub fn from_satp(satp: usize) -> Self {
@@ -88,7 +103,9 @@ impl PageTable {
         8usize << 60 | self.root_ppn.0
     }
 
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Finds the entry for `vpn` at `level`, making the tables above it.
+    /// Fails with `-ENOMEM` if a missing intermediate table cannot get a frame.
+    fn find_pte_create(&mut self, vpn: VirtPageNum, level: usize) -> Result<&mut PageTableEntry, isize> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
@@ -96,15 +113,16 @@ impl PageTable {
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
             
-            if i == 2 {
+            if i == MAX_LEVEL - level {
                 result = Some(pte);
                 break;
             }
 
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
             if !pte.is_valid() {
                 trace!("pte {:?} is invalid, alloc a frame", &pte);
 
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc().ok_or(-ENOMEM)?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame)
             }
@@ -112,21 +130,23 @@ impl PageTable {
             ppn = pte.ppn();
         }
 
-        result
+        Ok(result.unwrap())
     }
 
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Finds the leaf entry that maps `vpn`, with its level. Below a
+    /// huge page this is the entry at level 0, valid or not.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
+        let mut result: Option<(&mut PageTableEntry, usize)> = None;
 
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
 
-            if i == 2 {
+            if i == MAX_LEVEL || pte.is_leaf() {
                 debug!("vpn {:?}'s pte is {:?}", vpn , &pte);
 
-                result = Some(pte);
+                result = Some((pte, MAX_LEVEL - i));
                 break;
             }
 
@@ -142,28 +162,102 @@ impl PageTable {
         result
     }
 
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    /// Finds the entry of a 4 KiB page, for the operations that only
+    /// make sense on those.
+    fn find_page_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_pte(vpn).map(|(pte, level)| {
+            assert_eq!(level, 0, "vpn {:?} is inside a huge page", vpn);
+            pte
+        })
+    }
+
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Result<(), isize> {
+        let pte = self.find_pte_create(vpn, 0)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
 
         trace!("vpn {:?} is mapped to ppn {:?}", vpn, ppn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+
+    /// Maps `level_pages(level)` pages from `vpn` to those from `ppn` with
+    /// one leaf entry at `level`, 1 or 2. Both must be aligned to that size,
+    /// and `flags` must include one of R, W or X.
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, level: usize) -> Result<(), isize> {
+        assert!((1..=MAX_LEVEL).contains(&level), "there are no huge pages at level {}", level);
+        assert!(
+            vpn.0.is_multiple_of(level_pages(level)) && ppn.0.is_multiple_of(level_pages(level)),
+            "vpn {:?} or ppn {:?} is not aligned for level {}", vpn, ppn, level
+        );
+        assert!(flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X));
+
+        let pte = self.find_pte_create(vpn, level)?;
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+
+        trace!("vpn {:?} is mapped to ppn {:?} at level {}", vpn, ppn, level);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
     }
 
+    /// Unmaps the page, or the huge page, that starts at `vpn`. Tables
+    /// below a huge page stay in place.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte(vpn).unwrap();
+        let (pte, level) = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is unmapped before unmapping", vpn);
+        assert!(vpn.0.is_multiple_of(level_pages(level)), "vpn {:?} is in the middle of a huge page", vpn);
 
         trace!("vpn {:?} is unmapped", vpn);
         *pte = PageTableEntry::empty();
     }
 
+    /// Points an already mapped `vpn` at `ppn` with new flags. The `A` and
+    /// `D` bits are kept, since swap relies on them.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_page_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is unmapped before remapping", vpn);
+
+        trace!("vpn {:?} is remapped to ppn {:?} with {:?}", vpn, ppn, flags);
+        let kept = pte.flags() & (PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(ppn, flags | kept | PTEFlags::V);
+    }
+
+    /// Clears the `A` bit of a mapped `vpn`, for the clock to see whether
+    /// the page gets used again.
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_page_pte(vpn).unwrap();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+    }
+
+    /// Clears the `D` bit of a mapped `vpn`, once what the page holds is
+    /// saved elsewhere.
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_page_pte(vpn).unwrap();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::D);
+    }
+
+    /// Sets the bits the hardware would for an access to a mapped `vpn`,
+    /// for when the kernel accesses the page through its physical address.
+    /// On a huge page they stand for all of it.
+    pub fn mark_used(&mut self, vpn: VirtPageNum, is_write: bool) {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        let used = if is_write { PTEFlags::A | PTEFlags::D } else { PTEFlags::A };
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() | used);
+    }
+
+    /// The entry `vpn` would have as a 4 KiB page: inside a huge page, its
+    /// flags with the frame `vpn` falls on.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| *pte)
+        self.find_pte(vpn).map(|(pte, level)| {
+            if level == 0 || !pte.is_valid() {
+                return *pte;
+            }
+            let ppn = PhysPageNum(pte.ppn().0 + vpn.0 % level_pages(level));
+            PageTableEntry::new(ppn, pte.flags())
+        })
     }
 
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        self.translate(va.clone().floor()).map(|pte| {
             let aligned_pa: PhysAddr  = pte.ppn().into();
             let offset = va.page_offset();
             let aligned_pa_usize: usize = aligned_pa.into();
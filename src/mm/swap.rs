@@ -0,0 +1,124 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use lazy_static::lazy_static;
+use log::{info, trace};
+
+use crate::{
+    config::{PAGE_SIZE, SWAP_HIGH_WATERMARK, SWAP_LOW_WATERMARK},
+    drivers::{SECTOR_SIZE, VIRTIO_BLOCK},
+    sync::UPIntrFreeCell,
+};
+
+use super::{address::PhysPageNum, frame_allocator::frame_free_count};
+
+const SECTORS_PER_SLOT: usize = PAGE_SIZE / SECTOR_SIZE;
+
+/// Page-sized slots on the swap device.
+struct SwapSpace {
+    slots: usize,
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapSpace {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current < self.slots {
+            self.current += 1;
+            Some(self.current - 1)
+        } else {
+            None
+        }
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        assert!(slot < self.current && !self.recycled.contains(&slot), "swap slot {} is not in use", slot);
+        self.recycled.push(slot);
+    }
+}
+
+lazy_static! {
+    /// The whole virtio block device is used as swap, if there is one.
+    static ref SWAP_SPACE: Option<UPIntrFreeCell<SwapSpace>> = VIRTIO_BLOCK.as_ref().map(|device| unsafe {
+        UPIntrFreeCell::new(SwapSpace {
+            slots: device.exclusive_access().capacity() / SECTORS_PER_SLOT,
+            current: 0,
+            recycled: Vec::new(),
+        })
+    });
+}
+
+pub fn init_swap() {
+    match SWAP_SPACE.as_ref() {
+        Some(swap) => info!("swap has {} slots", swap.exclusive_access().slots),
+        None => info!("no swap device, pages stay in memory"),
+    }
+}
+
+/// Whether pages should be swapped out, and how many to get free frames
+/// back up to the high watermark.
+pub fn frames_wanted() -> usize {
+    let free = frame_free_count();
+    if SWAP_SPACE.is_none() || free >= SWAP_LOW_WATERMARK {
+        return 0;
+    }
+    SWAP_HIGH_WATERMARK - free
+}
+
+pub fn swap_alloc() -> Option<usize> {
+    SWAP_SPACE.as_ref()?.exclusive_access().alloc()
+}
+
+pub fn swap_free(slot: usize) {
+    SWAP_SPACE.as_ref().unwrap().exclusive_access().dealloc(slot);
+}
+
+pub fn swap_write(slot: usize, ppn: PhysPageNum) {
+    trace!("ppn {:?} is written to swap slot {}", ppn, slot);
+    VIRTIO_BLOCK
+        .as_ref()
+        .unwrap()
+        .exclusive_access()
+        .write_sectors(slot * SECTORS_PER_SLOT, ppn.get_byte_array());
+}
+
+pub fn swap_read(slot: usize, ppn: PhysPageNum) {
+    trace!("swap slot {} is read into ppn {:?}", slot, ppn);
+    VIRTIO_BLOCK
+        .as_ref()
+        .unwrap()
+        .exclusive_access()
+        .read_sectors(slot * SECTORS_PER_SLOT, ppn.get_byte_array());
+}
+
+lazy_static! {
+    /// Frames the kernel holds on to through their physical address, with
+    /// how many times each is pinned.
+    static ref PINNED_FRAMES: UPIntrFreeCell<BTreeMap<PhysPageNum, usize>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+/// Keeps a frame from being swapped out while it lives.
+pub struct FramePin(PhysPageNum);
+
+impl FramePin {
+    pub fn new(ppn: PhysPageNum) -> Self {
+        *PINNED_FRAMES.exclusive_access().entry(ppn).or_insert(0) += 1;
+        Self(ppn)
+    }
+}
+
+impl Drop for FramePin {
+    fn drop(&mut self) {
+        let mut pinned = PINNED_FRAMES.exclusive_access();
+        let count = pinned.get_mut(&self.0).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            pinned.remove(&self.0);
+        }
+    }
+}
+
+pub fn is_pinned(ppn: PhysPageNum) -> bool {
+    PINNED_FRAMES.exclusive_access().contains_key(&ppn)
+}
@@ -0,0 +1,182 @@
+use core::{marker::PhantomData, mem::size_of};
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{config::VA_WIDTH, syscall::errno::EFAULT, task::handle_current_page_fault};
+
+use super::{address::{PhysAddr, StepByOne, VirtAddr}, page_table::{PTEFlags, PageTable}, swap::FramePin};
+
+/// Where the user half of Sv39 ends. `VirtAddr` keeps only the low bits,
+/// so anything above has to be turned away before it is translated.
+const USER_SPACE_END: usize = 1 << (VA_WIDTH - 1);
+
+/// Translates a user address, requiring its page to be valid, user
+/// accessible and to carry every bit in `flags`. A page the current task
+/// could legally fault in, e.g. a copy-on-write or lazy page, is fixed up first, so
+/// callers must not hold the current task's inner lock. The page is marked
+/// used, so that swap does not take a page the kernel wrote for clean.
+fn translate_user_va(page_table: &mut PageTable, va: usize, flags: PTEFlags) -> Result<PhysAddr, isize> {
+    if va >= USER_SPACE_END {
+        return Err(-EFAULT);
+    }
+    let va = VirtAddr::from(va);
+    let accessible = |page_table: &PageTable| {
+        page_table
+            .translate(va.floor())
+            .is_some_and(|pte| pte.is_valid() && pte.flags().contains(flags | PTEFlags::U))
+    };
+
+    if !accessible(page_table) && !(handle_current_page_fault(va, flags.contains(PTEFlags::W)) && accessible(page_table)) {
+        return Err(-EFAULT);
+    }
+    page_table.mark_used(va.floor(), flags.contains(PTEFlags::W));
+    page_table.translate_va(va).ok_or(-EFAULT)
+}
+
+/// Resolves a user address to the physical address backing it, e.g. to
+/// tell futex words apart no matter which address space they are seen from.
+/// Asking for `writable` breaks copy-on-write sharing first.
+pub fn translate_user_addr(token: usize, va: usize, writable: bool) -> Result<usize, isize> {
+    let flags = if writable { PTEFlags::R | PTEFlags::W } else { PTEFlags::R };
+    translate_user_va(&mut PageTable::from_satp(token), va, flags).map(|pa| pa.0)
+}
+
+/// Like `translate_user_addr`, but also pins the frame, so that it keeps
+/// backing `va` while the kernel faults in other user pages, which may
+/// swap pages out.
+pub fn pin_user_addr(token: usize, va: usize, writable: bool) -> Result<(usize, FramePin), isize> {
+    let pa = translate_user_addr(token, va, writable)?;
+    Ok((pa, FramePin::new(PhysAddr::from(pa).floor())))
+}
+
+/// Splits `[ptr, ptr + len)` into per-page kernel slices after checking
+/// that every page is mapped for the user with `flags`. Each page is pinned
+/// as soon as it is translated, as faulting in the next one may reclaim.
+fn translate_user_buffer(token: usize, ptr: usize, len: usize, flags: PTEFlags) -> Result<UserBuffer, isize> {
+    let mut page_table = PageTable::from_satp(token);
+    let mut start = ptr;
+    let end = ptr.checked_add(len).filter(|&end| end <= USER_SPACE_END).ok_or(-EFAULT)?;
+    let mut buffers = Vec::new();
+    let mut pins = Vec::new();
+
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut next_vpn = start_va.floor();
+        next_vpn.step();
+        let page_end: usize = VirtAddr::from(next_vpn).into();
+        let chunk_end = end.min(page_end);
+
+        let pa = translate_user_va(&mut page_table, start, flags)?;
+        pins.push(FramePin::new(pa.floor()));
+        buffers.push(unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, chunk_end - start) });
+        start = chunk_end;
+    }
+
+    Ok(UserBuffer { buffers, _pins: pins })
+}
+
+/// A user memory range, seen by the kernel as a list of page slices.
+/// Its frames are pinned, so they are not swapped out from under a file
+/// that blocks while holding it.
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+    _pins: Vec<FramePin>,
+}
+
+impl UserBuffer {
+    /// A buffer the kernel will read from, e.g. for `write`.
+    pub fn new_readable(token: usize, ptr: *const u8, len: usize) -> Result<Self, isize> {
+        translate_user_buffer(token, ptr as usize, len, PTEFlags::R)
+    }
+
+    /// A buffer the kernel will write into, e.g. for `read`.
+    pub fn new_writable(token: usize, ptr: *mut u8, len: usize) -> Result<Self, isize> {
+        translate_user_buffer(token, ptr as usize, len, PTEFlags::W)
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = &'static mut [u8];
+
+    type IntoIter = alloc::vec::IntoIter<&'static mut [u8]>;
+
+    /// Unpins the frames, so the slices must be used up before blocking.
+    fn into_iter(self) -> Self::IntoIter {
+        self.buffers.into_iter()
+    }
+}
+
+pub fn copy_from_user(token: usize, dst: &mut [u8], src: *const u8) -> Result<(), isize> {
+    let mut start = 0;
+    for buffer in UserBuffer::new_readable(token, src, dst.len())? {
+        dst[start..start + buffer.len()].copy_from_slice(buffer);
+        start += buffer.len();
+    }
+    Ok(())
+}
+
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) -> Result<(), isize> {
+    let mut start = 0;
+    for buffer in UserBuffer::new_writable(token, dst, src.len())? {
+        buffer.copy_from_slice(&src[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    Ok(())
+}
+
+/// Reads a NUL-terminated string from user memory.
+pub fn read_user_cstr(token: usize, ptr: *const u8) -> Result<String, isize> {
+    let mut page_table = PageTable::from_satp(token);
+    let mut bytes = Vec::new();
+    let mut va = ptr as usize;
+
+    loop {
+        let pa = translate_user_va(&mut page_table, va, PTEFlags::R)?;
+        let c: u8 = *pa.get_ref();
+        if c == 0 {
+            break;
+        }
+        bytes.push(c);
+        va = va.checked_add(1).ok_or(-EFAULT)?;
+    }
+
+    String::from_utf8(bytes).map_err(|_| -EFAULT)
+}
+
+/// A typed pointer into user memory. Values are copied byte-wise, so `T`
+/// may straddle a page boundary and need not be aligned.
+pub struct UserPtr<T> {
+    token: usize,
+    ptr: *mut T,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(token: usize, ptr: *mut T) -> Self {
+        Self {
+            token,
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn read(&self) -> Result<T, isize> {
+        let mut value = core::mem::MaybeUninit::<T>::uninit();
+        let dst = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        copy_from_user(self.token, dst, self.ptr as *const u8)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    pub fn write(&self, value: T) -> Result<(), isize> {
+        let src = unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        copy_to_user(self.token, self.ptr as *mut u8, src)
+    }
+}
@@ -5,6 +5,11 @@ pub fn console_putchar(c: usize) {
     sbi_rt::legacy::console_putchar(c);
 }
 
+pub fn console_getchar() -> usize {
+    #[allow(deprecated)]
+    sbi_rt::legacy::console_getchar()
+}
+
 pub fn shutdown(failure: bool) -> ! {
     if !failure {
         system_reset(Shutdown, NoReason);
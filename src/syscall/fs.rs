@@ -1,19 +1,75 @@
-pub fn sys_openat() -> isize {
-    0
+use crate::{fs::{open_file, OpenFlags}, mm::{read_user_cstr, UserBuffer}, task::current_process};
+
+use super::errno::{EBADF, ENOENT, EROFS};
+
+/// There is only the root directory, so `dirfd` makes no difference.
+pub fn sys_openat(_dirfd: isize, path: *const u8, flags: u32, _mode: u32) -> isize {
+    let process = current_process().unwrap();
+    let token = process.inner_exclusive_access().get_user_token();
+    let path = match read_user_cstr(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let flags = OpenFlags::from_bits_truncate(flags);
+    // Files are the images linked into the kernel, there are no others
+    // and they keep their size.
+    if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) {
+        return -EROFS;
+    }
+    let Some(inode) = open_file(&path, flags) else {
+        return -ENOENT;
+    };
+
+    let mut inner = process.inner_exclusive_access();
+    let fd = inner.alloc_fd();
+    inner.fd_table[fd] = Some(inode);
+    fd as isize
 }
 
-pub fn sys_close() -> isize {
-    0
+pub fn sys_close(fd: usize) -> isize {
+    let process = current_process().unwrap();
+    let mut inner = process.inner_exclusive_access();
+    match inner.fd_table.get_mut(fd) {
+        Some(file @ Some(_)) => {
+            *file = None;
+            0
+        }
+        _ => -EBADF,
+    }
 }
 
 pub fn sys_lseek() -> isize {
     0
 }
 
-pub fn sys_read() -> isize {
-    0
+pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let process = current_process().unwrap();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) if file.readable() => file.clone(),
+        _ => return -EBADF,
+    };
+    let token = inner.get_user_token();
+    drop(inner);
+
+    match UserBuffer::new_writable(token, buf, len) {
+        Ok(buffer) => file.read(buffer) as isize,
+        Err(errno) => errno,
+    }
 }
 
-pub fn sys_write() -> isize {
-    0
+pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+    let process = current_process().unwrap();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) if file.writable() => file.clone(),
+        _ => return -EBADF,
+    };
+    let token = inner.get_user_token();
+    drop(inner);
+
+    match UserBuffer::new_readable(token, buf, len) {
+        Ok(buffer) => file.write(buffer) as isize,
+        Err(errno) => errno,
+    }
 }
@@ -0,0 +1,196 @@
+use crate::{config::{PAGE_SIZE, USER_STACK_TOP}, mm::{MapArea, MapPermission, MapType, VirtAddr, VirtPageNum}, task::current_process};
+
+use super::errno::{EACCES, EBADF, EEXIST, EINVAL, ENODEV, ENOMEM, EOVERFLOW, EPERM};
+
+const PROT_READ: usize              = 0x1;
+const PROT_WRITE: usize             = 0x2;
+const PROT_EXEC: usize              = 0x4;
+
+const MAP_SHARED: usize             = 0x01;
+const MAP_PRIVATE: usize            = 0x02;
+const MAP_SHARED_VALIDATE: usize    = 0x03;
+const MAP_TYPE: usize               = 0x0f;
+const MAP_FIXED: usize              = 0x10;
+const MAP_ANONYMOUS: usize          = 0x20;
+const MAP_POPULATE: usize           = 0x8000;
+const MAP_FIXED_NOREPLACE: usize    = 0x100000;
+
+const MS_ASYNC: usize               = 0x1;
+const MS_INVALIDATE: usize          = 0x2;
+const MS_SYNC: usize                = 0x4;
+
+/// Pages below this are never mapped, so that null pointers fault.
+const MMAP_MIN_ADDR: usize = PAGE_SIZE;
+
+fn prot_to_permission(prot: usize) -> Result<MapPermission, isize> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(-EINVAL);
+    }
+    let mut map_perm = MapPermission::U;
+    if prot & PROT_READ != 0 {
+        map_perm |= MapPermission::R;
+    }
+    // RISC-V has no write-only pages, so writable implies readable.
+    if prot & PROT_WRITE != 0 {
+        map_perm |= MapPermission::R | MapPermission::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        map_perm |= MapPermission::X;
+    }
+    Ok(map_perm)
+}
+
+/// Checks that `[addr, addr + len)` is page aligned at the start and lies
+/// in user space; returns its page range, rounded up at the end.
+fn user_page_range(addr: usize, len: usize) -> Result<(VirtPageNum, VirtPageNum), isize> {
+    if !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(-EINVAL);
+    }
+    let end = addr.checked_add(len).filter(|&end| end <= USER_STACK_TOP).ok_or(-EINVAL)?;
+    Ok((VirtAddr::from(addr).floor(), VirtAddr::from(end).ceil()))
+}
+
+/// Returns the new break, or the old one if it cannot be moved; brk(0)
+/// thus reports the current break, which is how libc finds it.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process().unwrap();
+    let brk = process.inner_exclusive_access().memory_set.set_brk(addr);
+    brk as isize
+}
+
+/// Every mapping is populated lazily: anonymous private ones with fresh
+/// frames, anonymous shared ones from a page cache of their own so fork can
+/// share them, and files from their page cache.
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: isize, offset: usize) -> isize {
+    if len == 0 || !offset.is_multiple_of(PAGE_SIZE) {
+        return -EINVAL;
+    }
+    let map_perm = match prot_to_permission(prot) {
+        Ok(map_perm) => map_perm,
+        Err(errno) => return errno,
+    };
+    let shared = match flags & MAP_TYPE {
+        MAP_SHARED | MAP_SHARED_VALIDATE => true,
+        MAP_PRIVATE => false,
+        _ => return -EINVAL,
+    };
+    if len > USER_STACK_TOP {
+        return -ENOMEM;
+    }
+    let pages = len.div_ceil(PAGE_SIZE);
+    // The file offset of the last page has to be representable.
+    if offset.checked_add(pages * PAGE_SIZE).is_none() {
+        return -EOVERFLOW;
+    }
+
+    let process = current_process().unwrap();
+    let mut inner = process.inner_exclusive_access();
+    let page_cache = if flags & MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        let Some(Some(file)) = usize::try_from(fd).ok().and_then(|fd| inner.fd_table.get(fd)) else {
+            return -EBADF;
+        };
+        let Some(page_cache) = file.page_cache() else {
+            return -ENODEV;
+        };
+        if !file.readable() || (shared && prot & PROT_WRITE != 0 && !file.writable()) {
+            return -EACCES;
+        }
+        Some(page_cache)
+    };
+    let memory_set = &mut inner.memory_set;
+
+    let start_vpn = if flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0 {
+        let (start_vpn, end_vpn) = match user_page_range(addr, len) {
+            Ok(range) => range,
+            Err(errno) => return errno,
+        };
+        if addr < MMAP_MIN_ADDR {
+            return -EPERM;
+        }
+        if memory_set.overlaps(start_vpn, end_vpn) {
+            if flags & MAP_FIXED_NOREPLACE != 0 {
+                return -EEXIST;
+            }
+            memory_set.unmap_range(start_vpn, end_vpn);
+        }
+        start_vpn
+    } else {
+        // The hint is taken if the range there is free, as on Linux.
+        let hint = user_page_range(addr & !(PAGE_SIZE - 1), len)
+            .ok()
+            .filter(|&(start_vpn, end_vpn)| addr >= MMAP_MIN_ADDR && !memory_set.overlaps(start_vpn, end_vpn));
+        match hint {
+            Some((start_vpn, _)) => start_vpn,
+            None => match memory_set.find_free_area(pages) {
+                Some(start_vpn) => start_vpn,
+                None => return -ENOMEM,
+            },
+        }
+    };
+
+    let start_va = VirtAddr::from(start_vpn);
+    let end_va = VirtAddr::from(start_va.0 + pages * PAGE_SIZE);
+    let area = match page_cache {
+        Some(page_cache) => MapArea::new_file(start_va, end_va, map_perm, page_cache, offset / PAGE_SIZE, shared),
+        None if shared => MapArea::new_shared(start_va, end_va, map_perm),
+        None => MapArea::new(start_va, end_va, MapType::Lazy, map_perm),
+    };
+    if let Err(errno) = memory_set.push(area, None) {
+        return errno;
+    }
+    // As on Linux, pages that cannot be populated are faulted in later.
+    if flags & MAP_POPULATE != 0 {
+        let _ = memory_set.populate(start_va, end_va);
+    }
+    start_va.0 as isize
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    if len == 0 {
+        return -EINVAL;
+    }
+    let (start_vpn, end_vpn) = match user_page_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    current_process().unwrap().inner_exclusive_access().memory_set.unmap_range(start_vpn, end_vpn);
+    0
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let map_perm = match prot_to_permission(prot) {
+        Ok(map_perm) => map_perm,
+        Err(errno) => return errno,
+    };
+    let (start_vpn, end_vpn) = match user_page_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    let process = current_process().unwrap();
+    let result = process.inner_exclusive_access().memory_set.protect_range(start_vpn, end_vpn, map_perm);
+    match result {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+/// Writes what shared file mappings in the range wrote back to their
+/// files. That is a copy in memory, so `MS_ASYNC` is as good as `MS_SYNC`.
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> isize {
+    if flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0 || flags & (MS_ASYNC | MS_SYNC) == MS_ASYNC | MS_SYNC {
+        return -EINVAL;
+    }
+    let (start_vpn, end_vpn) = match user_page_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    let process = current_process().unwrap();
+    let memory_set = &mut process.inner_exclusive_access().memory_set;
+    if !memory_set.covers(start_vpn, end_vpn) {
+        return -ENOMEM;
+    }
+    memory_set.write_back_range(start_vpn, end_vpn);
+    0
+}
@@ -1,8 +1,20 @@
+pub mod errno;
 mod fs;
+mod mm;
 mod process;
+mod signal;
+mod sync;
 
+use log::debug;
+
+use crate::task::{SigAction, SigSet};
+
+use errno::ENOSYS;
 use fs::*;
+use mm::*;
 use process::*;
+use signal::*;
+use sync::*;
 
 const SYS_OPENAT: usize             = 56;
 const SYS_CLOSE: usize              = 57;
@@ -11,21 +23,118 @@ const SYS_READ: usize               = 63;
 const SYS_WRITE: usize              = 64;
 
 const SYS_EXIT: usize               = 93;
+const SYS_EXIT_GROUP: usize         = 94;
+const SYS_SET_TID_ADDRESS: usize    = 96;
+const SYS_FUTEX: usize              = 98;
+const SYS_SCHED_SETSCHEDULER: usize = 119;
+const SYS_SCHED_GETSCHEDULER: usize = 120;
 const SYS_SCHED_YIELD: usize        = 124;
+const SYS_KILL: usize               = 129;
+const SYS_TGKILL: usize             = 131;
+const SYS_RT_SIGACTION: usize       = 134;
+const SYS_RT_SIGPROCMASK: usize     = 135;
+const SYS_RT_SIGRETURN: usize       = 139;
+const SYS_SETPRIORITY: usize        = 140;
+const SYS_GETPRIORITY: usize        = 141;
+const SYS_GETRUSAGE: usize          = 165;
 const SYS_GETTIMEOFDAY: usize       = 169;
+const SYS_GETPID: usize             = 172;
+const SYS_GETPPID: usize            = 173;
+const SYS_GETTID: usize             = 178;
+const SYS_BRK: usize                = 214;
+const SYS_MUNMAP: usize             = 215;
+const SYS_CLONE: usize              = 220;
+const SYS_EXECVE: usize             = 221;
+const SYS_MMAP: usize               = 222;
+const SYS_MPROTECT: usize           = 226;
+const SYS_MSYNC: usize              = 227;
+const SYS_WAIT4: usize              = 260;
 
-pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
+fn syscall_name(syscall_id: usize) -> &'static str {
     match syscall_id {
-        SYS_OPENAT              => sys_openat(), 
-        SYS_CLOSE               => sys_close(),
+        SYS_OPENAT              => "openat",
+        SYS_CLOSE               => "close",
+        SYS_LSEEK               => "lseek",
+        SYS_READ                => "read",
+        SYS_WRITE               => "write",
+
+        SYS_EXIT                => "exit",
+        SYS_EXIT_GROUP          => "exit_group",
+        SYS_SET_TID_ADDRESS     => "set_tid_address",
+        SYS_FUTEX               => "futex",
+        SYS_SCHED_SETSCHEDULER  => "sched_setscheduler",
+        SYS_SCHED_GETSCHEDULER  => "sched_getscheduler",
+        SYS_SCHED_YIELD         => "sched_yield",
+        SYS_KILL                => "kill",
+        SYS_TGKILL              => "tgkill",
+        SYS_RT_SIGACTION        => "rt_sigaction",
+        SYS_RT_SIGPROCMASK      => "rt_sigprocmask",
+        SYS_RT_SIGRETURN        => "rt_sigreturn",
+        SYS_SETPRIORITY         => "setpriority",
+        SYS_GETPRIORITY         => "getpriority",
+        SYS_GETRUSAGE           => "getrusage",
+        SYS_GETTIMEOFDAY        => "gettimeofday",
+        SYS_GETPID              => "getpid",
+        SYS_GETPPID             => "getppid",
+        SYS_GETTID              => "gettid",
+        SYS_BRK                 => "brk",
+        SYS_MUNMAP              => "munmap",
+        SYS_CLONE               => "clone",
+        SYS_EXECVE              => "execve",
+        SYS_MMAP                => "mmap",
+        SYS_MPROTECT            => "mprotect",
+        SYS_MSYNC               => "msync",
+        SYS_WAIT4               => "wait4",
+
+        _                       => "unknown",
+    }
+}
+
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
+    let result = match syscall_id {
+        SYS_OPENAT              => sys_openat(args[0] as isize, args[1] as *const u8, args[2] as u32, args[3] as u32),
+        SYS_CLOSE               => sys_close(args[0]),
         SYS_LSEEK               => sys_lseek(),
-        SYS_READ                => sys_read(),
-        SYS_WRITE               => sys_write(),
+        SYS_READ                => sys_read(args[0], args[1] as *mut u8, args[2]),
+        SYS_WRITE               => sys_write(args[0], args[1] as *const u8, args[2]),
 
-        SYS_EXIT                => sys_exit(),
+        SYS_EXIT                => sys_exit(args[0] as i32),
+        SYS_EXIT_GROUP          => sys_exit_group(args[0] as i32),
+        SYS_SET_TID_ADDRESS     => sys_set_tid_address(args[0] as *mut i32),
+        SYS_FUTEX               => sys_futex(args[0] as *mut u32, args[1], args[2] as u32, args[3], args[4] as *mut u32, args[5] as u32),
+        SYS_SCHED_SETSCHEDULER  => sys_sched_setscheduler(args[0], args[1], args[2] as *const i32),
+        SYS_SCHED_GETSCHEDULER  => sys_sched_getscheduler(args[0]),
         SYS_SCHED_YIELD         => sys_sched_yield(),
+        SYS_KILL                => sys_kill(args[0] as isize, args[1]),
+        SYS_TGKILL              => sys_tgkill(args[0] as isize, args[1] as isize, args[2]),
+        SYS_RT_SIGACTION        => sys_rt_sigaction(args[0], args[1] as *const SigAction, args[2] as *mut SigAction, args[3]),
+        SYS_RT_SIGPROCMASK      => sys_rt_sigprocmask(args[0], args[1] as *const SigSet, args[2] as *mut SigSet, args[3]),
+        SYS_RT_SIGRETURN        => sys_rt_sigreturn(),
+        SYS_SETPRIORITY         => sys_setpriority(args[0], args[1], args[2] as isize),
+        SYS_GETPRIORITY         => sys_getpriority(args[0], args[1]),
+        SYS_GETRUSAGE           => sys_getrusage(args[0] as isize, args[1] as *mut Rusage),
         SYS_GETTIMEOFDAY        => sys_gettimeofday(),
+        SYS_GETPID              => sys_getpid(),
+        SYS_GETPPID             => sys_getppid(),
+        SYS_GETTID              => sys_gettid(),
+        SYS_BRK                 => sys_brk(args[0]),
+        SYS_MUNMAP              => sys_munmap(args[0], args[1]),
+        SYS_CLONE               => sys_clone(args[0], args[1], args[2] as *mut i32, args[3], args[4] as *mut i32),
+        SYS_EXECVE              => sys_execve(args[0] as *const u8, args[1] as *const usize, args[2] as *const usize),
+        SYS_MMAP                => sys_mmap(args[0], args[1], args[2], args[3], args[4] as isize, args[5]),
+        SYS_MPROTECT            => sys_mprotect(args[0], args[1], args[2]),
+        SYS_MSYNC               => sys_msync(args[0], args[1], args[2]),
+        SYS_WAIT4               => sys_wait4(args[0] as isize, args[1] as *mut i32, args[2], args[3] as *mut Rusage),
 
-        _                       => panic!("Unsupport syscall_id: {}", syscall_id),
-    }
+        _                       => -ENOSYS,
+    };
+
+    debug!(
+        "syscall {}[{}]({:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x}) = {}",
+        syscall_name(syscall_id), syscall_id,
+        args[0], args[1], args[2], args[3], args[4], args[5],
+        result
+    );
+
+    result
 }
@@ -1,11 +1,384 @@
-pub fn sys_exit() -> isize {
-    0
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use crate::{config::CLOCK_FREQ, fs::{open_file, File, OpenFlags}, mm::{read_user_cstr, UserPtr}, task::{add_task, block_current_and_run_next, current_interrupted, current_process, current_task, current_user_token, exit_current_and_run_next, exit_group_current_and_run_next, pid2task, remove_task, suspend_current_and_run_next, ProcessControlBlock, SchedPolicy, TaskControlBlock, TaskStats, NICE_MAX, NICE_MIN, NSIG}};
+
+use super::errno::{ECHILD, EINTR, EINVAL, ENOENT, ESRCH};
+
+const PRIO_PROCESS: usize = 0;
+
+/// Low byte of the clone flags: the signal sent to the parent on exit.
+const CSIGNAL: usize              = 0xff;
+const CLONE_VM: usize             = 0x100;
+const CLONE_FS: usize             = 0x200;
+const CLONE_FILES: usize          = 0x400;
+const CLONE_SIGHAND: usize        = 0x800;
+const CLONE_VFORK: usize          = 0x4000;
+const CLONE_THREAD: usize         = 0x10000;
+const CLONE_SYSVSEM: usize        = 0x40000;
+const CLONE_SETTLS: usize         = 0x80000;
+const CLONE_PARENT_SETTID: usize  = 0x100000;
+const CLONE_CHILD_CLEARTID: usize = 0x200000;
+const CLONE_DETACHED: usize       = 0x400000;
+const CLONE_CHILD_SETTID: usize   = 0x1000000;
+
+/// Everything musl passes for fork, vfork and pthread_create.
+const CLONE_SUPPORTED: usize = CSIGNAL | CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND
+    | CLONE_VFORK | CLONE_THREAD | CLONE_SYSVSEM | CLONE_SETTLS | CLONE_PARENT_SETTID
+    | CLONE_CHILD_CLEARTID | CLONE_DETACHED | CLONE_CHILD_SETTID;
+
+const WNOHANG: usize = 1;
+
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    fn from_cycles(cycles: usize) -> Self {
+        Self {
+            sec: cycles / CLOCK_FREQ,
+            usec: cycles % CLOCK_FREQ * 1_000_000 / CLOCK_FREQ,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TimeSpec {
+    pub sec: isize,
+    pub nsec: isize,
+}
+
+impl TimeSpec {
+    /// The duration in clock cycles, or `None` if it is malformed.
+    pub fn to_cycles(self) -> Option<usize> {
+        if self.sec < 0 || !(0..1_000_000_000).contains(&self.nsec) {
+            return None;
+        }
+        let cycles = (self.sec as usize).checked_mul(CLOCK_FREQ)?;
+        cycles.checked_add(self.nsec as usize * CLOCK_FREQ / 1_000_000_000)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Rusage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    pub maxrss: isize,
+    pub ixrss: isize,
+    pub idrss: isize,
+    pub isrss: isize,
+    pub minflt: isize,
+    pub majflt: isize,
+    pub nswap: isize,
+    pub inblock: isize,
+    pub oublock: isize,
+    pub msgsnd: isize,
+    pub msgrcv: isize,
+    pub nsignals: isize,
+    pub nvcsw: isize,
+    pub nivcsw: isize,
+}
+
+impl Rusage {
+    fn from_stats(stats: &TaskStats) -> Self {
+        Self {
+            utime: TimeVal::from_cycles(stats.user_time),
+            stime: TimeVal::from_cycles(stats.kernel_time),
+            nvcsw: stats.voluntary_switches as isize,
+            nivcsw: stats.involuntary_switches as isize,
+            ..Default::default()
+        }
+    }
+}
+
+/// Looks up the task a scheduling syscall refers to; 0 means the caller.
+fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        current_task()
+    } else {
+        pid2task(pid)
+    }
+}
+
+pub fn sys_exit(exit_code: i32) -> ! {
+    exit_current_and_run_next(exit_code);
+    unreachable!();
+}
+
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    exit_group_current_and_run_next(exit_code);
+    unreachable!();
+}
+
+/// Reaps a zombie child. `pid` -1 waits for any child; so do 0 and other
+/// negative values, as there are no process groups.
+pub fn sys_wait4(pid: isize, wstatus: *mut i32, options: usize, rusage: *mut Rusage) -> isize {
+    let task = current_task().unwrap();
+    let process = task.process.clone();
+    let is_target = |child: &Arc<ProcessControlBlock>| pid <= 0 || child.getpid() == pid as usize;
+
+    loop {
+        let mut inner = process.inner_exclusive_access();
+        if !inner.children.iter().any(is_target) {
+            return -ECHILD;
+        }
+
+        let zombie = inner.children
+            .iter()
+            .position(|child| is_target(child) && child.inner_exclusive_access().is_zombie);
+        let Some(idx) = zombie else {
+            if options & WNOHANG != 0 {
+                return 0;
+            }
+            if current_interrupted() {
+                return -EINTR;
+            }
+            inner.child_waiters.push(task.clone());
+            drop(inner);
+            block_current_and_run_next();
+            continue;
+        };
+
+        let child = inner.children.remove(idx);
+        let child_inner = child.inner_exclusive_access();
+        let exit_status = child_inner.exit_status;
+        let mut child_stats = child_inner.stats;
+        child_stats.accumulate(&child_inner.children_stats);
+        drop(child_inner);
+        inner.children_stats.accumulate(&child_stats);
+        let token = inner.get_user_token();
+        drop(inner);
+
+        if !wstatus.is_null() {
+            if let Err(errno) = UserPtr::new(token, wstatus).write(exit_status) {
+                return errno;
+            }
+        }
+        if !rusage.is_null() {
+            if let Err(errno) = UserPtr::new(token, rusage).write(Rusage::from_stats(&child_stats)) {
+                return errno;
+            }
+        }
+        return child.getpid() as isize;
+    }
 }
 
 pub fn sys_sched_yield() -> isize {
+    suspend_current_and_run_next();
     0
 }
 
 pub fn sys_gettimeofday() -> isize {
     0
 }
+
+pub fn sys_getpid() -> isize {
+    current_task().unwrap().getpid() as isize
+}
+
+pub fn sys_gettid() -> isize {
+    current_task().unwrap().gettid() as isize
+}
+
+pub fn sys_set_tid_address(tidptr: *mut i32) -> isize {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().clear_child_tid = tidptr as usize;
+    task.gettid() as isize
+}
+
+pub fn sys_getppid() -> isize {
+    current_process()
+        .unwrap()
+        .inner_exclusive_access()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid() as isize)
+}
+
+/// Creates a thread with `CLONE_THREAD`, otherwise a process with fork
+/// semantics. A vfork is a plain fork here, since copy-on-write makes that
+/// cheap enough. `stack`, if non-zero, becomes the child's sp.
+pub fn sys_clone(flags: usize, stack: usize, ptid: *mut i32, tls: usize, ctid: *mut i32) -> isize {
+    if flags & !CLONE_SUPPORTED != 0 {
+        return -EINVAL;
+    }
+    // Same rules as Linux: threads share signal handlers, which in turn
+    // need a shared address space.
+    if (flags & CLONE_THREAD != 0 && flags & CLONE_SIGHAND == 0)
+        || (flags & CLONE_SIGHAND != 0 && flags & CLONE_VM == 0) {
+        return -EINVAL;
+    }
+    // Separate processes sharing one address space are not supported.
+    if flags & CLONE_VM != 0 && flags & (CLONE_THREAD | CLONE_VFORK) == 0 {
+        return -EINVAL;
+    }
+    if flags & CSIGNAL > NSIG {
+        return -EINVAL;
+    }
+
+    let current = current_task().unwrap();
+    let process = current.process.clone();
+    let child = if flags & CLONE_THREAD != 0 {
+        process.new_thread(&current)
+    } else {
+        process.fork(&current, flags & CSIGNAL).map(|child_process| child_process.inner_exclusive_access().threads[0].clone())
+    };
+    let child = match child {
+        Ok(child) => child,
+        Err(errno) => return errno,
+    };
+    let child_tid = child.gettid();
+
+    let mut child_inner = child.inner_exclusive_access();
+    let trap_cx = child_inner.get_trap_cx();
+    // The child sees clone return 0.
+    trap_cx.gpr[10] = 0;
+    if stack != 0 {
+        trap_cx.set_sp(stack);
+    }
+    if flags & CLONE_SETTLS != 0 {
+        trap_cx.gpr[4] = tls;
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        child_inner.set_child_tid = ctid as usize;
+    }
+    if flags & CLONE_CHILD_CLEARTID != 0 {
+        child_inner.clear_child_tid = ctid as usize;
+    }
+    drop(child_inner);
+
+    if flags & CLONE_PARENT_SETTID != 0 {
+        // Like Linux, a bad pointer here does not fail the clone.
+        let _ = UserPtr::new(current_user_token(), ptid).write(child_tid as i32);
+    }
+
+    add_task(child);
+    child_tid as isize
+}
+
+/// Reads a NULL-terminated array of user string pointers, like argv.
+fn read_user_cstr_array(token: usize, ptr: *const usize) -> Result<Vec<String>, isize> {
+    let mut strings = Vec::new();
+    if ptr.is_null() {
+        return Ok(strings);
+    }
+
+    loop {
+        let str_ptr = UserPtr::new(token, unsafe { ptr.add(strings.len()) } as *mut usize).read()?;
+        if str_ptr == 0 {
+            break;
+        }
+        strings.push(read_user_cstr(token, str_ptr as *const u8)?);
+    }
+    Ok(strings)
+}
+
+pub fn sys_execve(path: *const u8, argv: *const usize, envp: *const usize) -> isize {
+    let token = current_user_token();
+    let args = read_user_cstr(token, path).and_then(|path| {
+        Ok((path, read_user_cstr_array(token, argv)?, read_user_cstr_array(token, envp)?))
+    });
+    let (path, argv, envp) = match args {
+        Ok(args) => args,
+        Err(errno) => return errno,
+    };
+
+    let Some(inode) = open_file(&path, OpenFlags::RDONLY) else {
+        return -ENOENT;
+    };
+    let elf_data = inode.read_all();
+    let task = current_task().unwrap();
+    task.process.exec(&task, &elf_data, inode.page_cache().as_ref(), argv, envp)
+}
+
+pub fn sys_setpriority(which: usize, who: usize, prio: isize) -> isize {
+    if which != PRIO_PROCESS {
+        return -EINVAL;
+    }
+    let Some(task) = find_task(who) else {
+        return -ESRCH;
+    };
+
+    // A ready task is queued by its old weight, so it goes back in anew.
+    let queued = remove_task(&task);
+    task.inner_exclusive_access().sched.nice = prio.clamp(NICE_MIN, NICE_MAX);
+    if queued {
+        add_task(task);
+    }
+    0
+}
+
+/// Returns `20 - nice` like Linux, so that the result is never negative.
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    if which != PRIO_PROCESS {
+        return -EINVAL;
+    }
+    let Some(task) = find_task(who) else {
+        return -ESRCH;
+    };
+
+    let nice = task.inner_exclusive_access().sched.nice;
+    20 - nice
+}
+
+pub fn sys_sched_setscheduler(pid: usize, policy: usize, param: *const i32) -> isize {
+    let Some(policy) = SchedPolicy::from_linux(policy) else {
+        return -EINVAL;
+    };
+    let priority = match UserPtr::new(current_user_token(), param as *mut i32).read() {
+        Ok(priority) => priority,
+        Err(errno) => return errno,
+    };
+    let valid = if policy.is_realtime() { (1..=99).contains(&priority) } else { priority == 0 };
+    if !valid {
+        return -EINVAL;
+    }
+    let Some(task) = find_task(pid) else {
+        return -ESRCH;
+    };
+
+    // A ready task may have to move between the real-time queue and the
+    // scheduler, or to another place in either.
+    let queued = remove_task(&task);
+    let mut inner = task.inner_exclusive_access();
+    inner.sched.policy = policy;
+    inner.sched.rt_priority = priority as usize;
+    drop(inner);
+    if queued {
+        add_task(task);
+    }
+    0
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    let Some(task) = find_task(pid) else {
+        return -ESRCH;
+    };
+
+    let policy = task.inner_exclusive_access().sched.policy;
+    policy.to_linux() as isize
+}
+
+pub fn sys_getrusage(who: isize, usage: *mut Rusage) -> isize {
+    let task = current_task().unwrap();
+    let rusage = match who {
+        RUSAGE_SELF => Rusage::from_stats(&task.process.inner_exclusive_access().total_stats()),
+        RUSAGE_CHILDREN => Rusage::from_stats(&task.process.inner_exclusive_access().children_stats),
+        RUSAGE_THREAD => Rusage::from_stats(&task.inner_exclusive_access().stats),
+        _ => return -EINVAL,
+    };
+
+    match UserPtr::new(current_user_token(), usage).write(rusage) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
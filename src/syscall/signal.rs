@@ -0,0 +1,140 @@
+use alloc::{sync::Arc, vec};
+
+use crate::{mm::UserPtr, task::{current_process, current_task, current_user_token, is_initproc, pid2process, pid2task, processes, send_signal_to_process, send_signal_to_task, sigreturn_current, SigAction, SigSet, SignalSource, NSIG, SIGKILL, SIGSTOP, SIG_IGN}};
+
+use super::errno::{EINVAL, ESRCH};
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// Only the 64-bit `sigset_t` the kernel itself uses is accepted.
+const SIGSET_SIZE: usize = core::mem::size_of::<SigSet>();
+
+/// Signal 0 passes, so that kill can probe for a process.
+fn check_signum(signum: usize) -> Result<(), isize> {
+    if signum > NSIG {
+        return Err(-EINVAL);
+    }
+    Ok(())
+}
+
+/// `pid` > 0 names a process, 0 the caller, and -1 everything but initproc
+/// and the caller. Process groups are not supported, so `-pgid` is taken to
+/// mean the process with that pid.
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    if let Err(errno) = check_signum(signum) {
+        return errno;
+    }
+    let current = current_process().unwrap();
+    let targets = match pid {
+        0 => vec![current.clone()],
+        -1 => processes()
+            .into_iter()
+            .filter(|process| !is_initproc(process) && !Arc::ptr_eq(process, &current))
+            .collect(),
+        _ => match pid2process(pid.unsigned_abs()) {
+            Some(process) => vec![process],
+            None => return -ESRCH,
+        },
+    };
+    if targets.is_empty() {
+        return -ESRCH;
+    }
+    if signum != 0 {
+        for process in targets {
+            send_signal_to_process(&process, signum, SignalSource::Kill(current.getpid()));
+        }
+    }
+    0
+}
+
+pub fn sys_tgkill(tgid: isize, tid: isize, signum: usize) -> isize {
+    if let Err(errno) = check_signum(signum) {
+        return errno;
+    }
+    if tgid <= 0 || tid <= 0 {
+        return -EINVAL;
+    }
+    let Some(task) = pid2task(tid as usize).filter(|task| task.getpid() == tgid as usize) else {
+        return -ESRCH;
+    };
+    if signum != 0 {
+        send_signal_to_task(&task, signum, SignalSource::Tkill(current_process().unwrap().getpid()));
+    }
+    0
+}
+
+pub fn sys_rt_sigaction(signum: usize, act: *const SigAction, oldact: *mut SigAction, sigsetsize: usize) -> isize {
+    if signum == 0 || signum > NSIG || sigsetsize != SIGSET_SIZE {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let new_action = if act.is_null() {
+        None
+    } else {
+        match UserPtr::new(token, act as *mut SigAction).read() {
+            Ok(action) => Some(action),
+            Err(errno) => return errno,
+        }
+    };
+    if new_action.is_some() && (signum == SIGKILL || signum == SIGSTOP) {
+        return -EINVAL;
+    }
+
+    let process = current_process().unwrap();
+    let old_action = process.inner_exclusive_access().sig_actions[signum - 1];
+    if !oldact.is_null() {
+        if let Err(errno) = UserPtr::new(token, oldact).write(old_action) {
+            return errno;
+        }
+    }
+    if let Some(mut action) = new_action {
+        action.mask = action.mask & !SigSet::unblockable();
+        let mut inner = process.inner_exclusive_access();
+        inner.sig_actions[signum - 1] = action;
+        // Like Linux, ignoring a signal discards it if it is pending.
+        if action.handler == SIG_IGN {
+            inner.sig_pending.remove(signum);
+        }
+    }
+    0
+}
+
+pub fn sys_rt_sigprocmask(how: usize, set: *const SigSet, oldset: *mut SigSet, sigsetsize: usize) -> isize {
+    if sigsetsize != SIGSET_SIZE {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let old_mask = task.inner_exclusive_access().sig_mask;
+
+    if !set.is_null() {
+        let set = match UserPtr::new(token, set as *mut SigSet).read() {
+            Ok(set) => set,
+            Err(errno) => return errno,
+        };
+        let new_mask = match how {
+            SIG_BLOCK => old_mask | set,
+            SIG_UNBLOCK => old_mask & !set,
+            SIG_SETMASK => set,
+            _ => return -EINVAL,
+        };
+        task.inner_exclusive_access().sig_mask = new_mask & !SigSet::unblockable();
+    }
+    if !oldset.is_null() {
+        if let Err(errno) = UserPtr::new(token, oldset).write(old_mask) {
+            return errno;
+        }
+    }
+    0
+}
+
+/// Returns the a0 of the interrupted context, so that the syscall path
+/// writing the result back leaves it untouched.
+pub fn sys_rt_sigreturn() -> isize {
+    match sigreturn_current() {
+        Ok(a0) => a0 as isize,
+        Err(errno) => errno,
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{drivers::get_time, mm::{pin_user_addr, FramePin, UserPtr}, task::{current_user_token, futex_requeue, futex_wait, futex_wake}};
+
+use super::{errno::{EAGAIN, EINVAL, ENOSYS}, process::TimeSpec};
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_REQUEUE: usize = 3;
+const FUTEX_CMP_REQUEUE: usize = 4;
+
+/// Futexes are keyed by physical address, so private ones need no special
+/// treatment and the flag is simply dropped.
+const FUTEX_PRIVATE_FLAG: usize = 128;
+const FUTEX_CLOCK_REALTIME: usize = 256;
+
+/// Checks the alignment of a futex word and returns its physical address,
+/// with its frame pinned so the key stays good while other user memory is
+/// touched. A word that waiters will sleep on is resolved for writing where
+/// the page allows it, so a copy-on-write page is split now rather than
+/// moving the futex under a sleeping waiter later. Waking only needs to
+/// read it.
+fn futex_key(token: usize, uaddr: *mut u32, will_wait: bool) -> Result<(usize, FramePin), isize> {
+    if !(uaddr as usize).is_multiple_of(core::mem::align_of::<u32>()) {
+        return Err(-EINVAL);
+    }
+    if will_wait {
+        if let Ok(key) = pin_user_addr(token, uaddr as usize, true) {
+            return Ok(key);
+        }
+    }
+    pin_user_addr(token, uaddr as usize, false)
+}
+
+/// `val2` shares its slot with `timeout` and is only used by the requeue
+/// operations, as the number of waiters to move.
+pub fn sys_futex(uaddr: *mut u32, futex_op: usize, val: u32, timeout: usize, uaddr2: *mut u32, val3: u32) -> isize {
+    let token = current_user_token();
+    let op = futex_op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+    if !matches!(op, FUTEX_WAIT | FUTEX_WAKE | FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) {
+        return -ENOSYS;
+    }
+    // Other user memory is read first: faulting it in may swap pages out,
+    // which must not happen to the futex page once its key is known.
+    let deadline = if op != FUTEX_WAIT || timeout == 0 {
+        None
+    } else {
+        let timeout = match UserPtr::new(token, timeout as *mut TimeSpec).read() {
+            Ok(timeout) => timeout,
+            Err(errno) => return errno,
+        };
+        match timeout.to_cycles() {
+            Some(cycles) => Some(get_time() + cycles),
+            None => return -EINVAL,
+        }
+    };
+    let (key, _pin) = match futex_key(token, uaddr, op == FUTEX_WAIT) {
+        Ok(key) => key,
+        Err(errno) => return errno,
+    };
+
+    match op {
+        FUTEX_WAIT => {
+            match UserPtr::new(token, uaddr).read() {
+                Ok(value) if value != val => -EAGAIN,
+                Ok(_) => futex_wait(key, deadline),
+                Err(errno) => errno,
+            }
+        }
+        FUTEX_WAKE => futex_wake(key, val as usize) as isize,
+        op @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+            let (key2, _pin2) = match futex_key(token, uaddr2, true) {
+                Ok(key2) => key2,
+                Err(errno) => return errno,
+            };
+            if op == FUTEX_CMP_REQUEUE {
+                match UserPtr::new(token, uaddr).read() {
+                    Ok(value) if value != val3 => return -EAGAIN,
+                    Ok(_) => {}
+                    Err(errno) => return errno,
+                }
+            }
+            futex_requeue(key, val as usize, key2, timeout) as isize
+        }
+        _ => unreachable!(),
+    }
+}
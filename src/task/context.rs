@@ -0,0 +1,27 @@
+use crate::trap::trap_return;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TaskContext {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    pub fn zero_init() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+
+    pub fn goto_trap_return(kernel_sp: usize) -> Self {
+        Self {
+            ra: trap_return as usize,
+            sp: kernel_sp,
+            s: [0; 12],
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use alloc::{collections::{BTreeMap, VecDeque}, sync::Arc};
+
+use lazy_static::lazy_static;
+
+use crate::{config::PAGE_SIZE, mm::{PhysAddr, PhysPageNum}, sync::UPIntrFreeCell, syscall::errno::{EINTR, ETIMEDOUT}};
+
+use super::{block_current_and_run_next, current_interrupted, current_task, task::TaskControlBlock, timer::{add_timer, remove_timer}, wakeup_task};
+
+lazy_static! {
+    /// Threads sleeping in FUTEX_WAIT, by the physical address of the futex
+    /// word, so that processes sharing memory also share queues.
+    static ref FUTEX_QUEUES: UPIntrFreeCell<BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+/// Whether anyone sleeps on a futex word in the frame at `ppn`. Such a
+/// frame must stay where it is, or a wake would miss the waiters.
+pub fn futex_waiters_on(ppn: PhysPageNum) -> bool {
+    let start = PhysAddr::from(ppn).0;
+    FUTEX_QUEUES.exclusive_access().range(start..start + PAGE_SIZE).next().is_some()
+}
+
+/// Takes `task` off whichever queue it is on. Returns whether it was queued.
+fn dequeue(task: &Arc<TaskControlBlock>) -> bool {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    let found = queues.iter_mut().find_map(|(&key, queue)| {
+        let idx = queue.iter().position(|waiter| Arc::ptr_eq(waiter, task))?;
+        queue.remove(idx);
+        Some(key)
+    });
+    if let Some(key) = found {
+        if queues[&key].is_empty() {
+            queues.remove(&key);
+        }
+    }
+    found.is_some()
+}
+
+/// Sleeps on the futex at `key` until woken by `futex_wake`, or until the
+/// `deadline` in clock cycles. The caller has already checked the futex
+/// word; nothing may switch tasks in between, or a wakeup could be lost.
+pub fn futex_wait(key: usize, deadline: Option<usize>) -> isize {
+    let task = current_task().unwrap();
+    FUTEX_QUEUES.exclusive_access().entry(key).or_default().push_back(task.clone());
+    if let Some(deadline) = deadline {
+        add_timer(deadline, task.clone());
+    }
+
+    block_current_and_run_next();
+
+    if deadline.is_some() {
+        remove_timer(&task);
+    }
+    // Whoever wakes a waiter takes it off the queue, so still being on one
+    // means the timer, or a signal, did it.
+    if !dequeue(&task) {
+        0
+    } else if current_interrupted() {
+        -EINTR
+    } else {
+        -ETIMEDOUT
+    }
+}
+
+/// Wakes up to `count` waiters of the futex at `key`; returns how many.
+pub fn futex_wake(key: usize, count: usize) -> usize {
+    futex_requeue(key, count, key, 0)
+}
+
+/// Wakes up to `wake_count` waiters of `key`, then moves up to
+/// `requeue_count` of the rest over to `key2`. Returns how many were woken
+/// plus how many were moved.
+pub fn futex_requeue(key: usize, wake_count: usize, key2: usize, requeue_count: usize) -> usize {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    let Some(mut queue) = queues.remove(&key) else {
+        return 0;
+    };
+
+    let woken: VecDeque<_> = queue.drain(..wake_count.min(queue.len())).collect();
+    let moved: VecDeque<_> = queue.drain(..requeue_count.min(queue.len())).collect();
+    let count = woken.len() + moved.len();
+    if !queue.is_empty() {
+        queues.insert(key, queue);
+    }
+    if !moved.is_empty() {
+        queues.entry(key2).or_default().extend(moved);
+    }
+    drop(queues);
+
+    for task in woken {
+        wakeup_task(task);
+    }
+    count
+}
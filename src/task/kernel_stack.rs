@@ -0,0 +1,89 @@
+use core::arch::asm;
+
+use lazy_static::lazy_static;
+use log::debug;
+
+use crate::{config::{KERNEL_STACK_SIZE, PAGE_SIZE, PAGE_SIZE_BITS, TRAMPOLINE}, mm::{MapPermission, VirtAddr, KERNEL_SPACE}, sync::UPIntrFreeCell};
+
+use super::recycle_allocator::RecycleAllocator;
+
+/// Each kernel stack owns a power-of-two sized slot of virtual memory, so
+/// the trap entry can tell a guard page apart with shifts alone. Whatever
+/// part of the slot is not stack is left unmapped as the guard, which is at
+/// least one page.
+pub const KERNEL_STACK_SLOT_BITS: usize =
+    (KERNEL_STACK_SIZE + PAGE_SIZE).next_power_of_two().trailing_zeros() as usize;
+const KERNEL_STACK_SLOT_SIZE: usize = 1 << KERNEL_STACK_SLOT_BITS;
+pub const KERNEL_STACK_PAGES: usize = KERNEL_STACK_SIZE >> PAGE_SIZE_BITS;
+
+lazy_static! {
+    static ref KSTACK_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
+        unsafe { UPIntrFreeCell::new(RecycleAllocator::new(0)) };
+}
+
+/// Returns `(bottom, top)` of the kernel stack with the given id. Stacks grow
+/// down from just below the trampoline, each with its guard below it.
+fn kernel_stack_position(id: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - id * KERNEL_STACK_SLOT_SIZE;
+    let bottom = top - KERNEL_STACK_SIZE;
+    (bottom, top)
+}
+
+/// Returns the id of the kernel stack whose guard `addr` falls into.
+pub fn kernel_stack_guard_owner(addr: usize) -> Option<usize> {
+    if addr >= TRAMPOLINE {
+        return None;
+    }
+    let offset = TRAMPOLINE - addr - 1;
+    let id = offset / KERNEL_STACK_SLOT_SIZE;
+    let in_guard = offset % KERNEL_STACK_SLOT_SIZE >= KERNEL_STACK_SIZE;
+    // Anything further down than a few thousand slots is not a kernel stack.
+    (in_guard && id < (1 << 16)).then_some(id)
+}
+
+pub struct KernelStack {
+    id: usize,
+}
+
+impl KernelStack {
+    /// Fails with `-ENOMEM` if there are no frames for the stack.
+    pub fn new() -> Result<Self, isize> {
+        let id = KSTACK_ALLOCATOR.exclusive_access().alloc();
+        let (bottom, top) = kernel_stack_position(id);
+
+        debug!("map kernel stack {} [{:#x}, {:#x})", id, bottom, top);
+        let result = KERNEL_SPACE.exclusive_access().insert_framed_area(
+            VirtAddr::from(bottom),
+            VirtAddr::from(top),
+            MapPermission::R | MapPermission::W
+        );
+        if let Err(errno) = result {
+            KSTACK_ALLOCATOR.exclusive_access().dealloc(id);
+            return Err(errno);
+        }
+
+        Ok(Self {
+            id,
+        })
+    }
+
+    pub fn get_top(&self) -> usize {
+        kernel_stack_position(self.id).1
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (bottom, _) = kernel_stack_position(self.id);
+
+        debug!("unmap kernel stack {}", self.id);
+        KERNEL_SPACE
+            .exclusive_access()
+            .remove_area_with_start_vpn(VirtAddr::from(bottom).into());
+        // The slot may be handed out again, so drop stale translations now.
+        unsafe {
+            asm!("sfence.vma");
+        }
+        KSTACK_ALLOCATOR.exclusive_access().dealloc(self.id);
+    }
+}
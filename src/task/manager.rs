@@ -0,0 +1,125 @@
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}, vec::Vec};
+use core::cmp::Reverse;
+
+use lazy_static::lazy_static;
+
+use crate::sync::UPIntrFreeCell;
+
+use super::{process::ProcessControlBlock, scheduler::{SchedPolicy, Scheduler, SchedulerImpl}, task::TaskControlBlock};
+
+pub struct TaskManager {
+    scheduler: SchedulerImpl,
+    /// SCHED_FIFO/SCHED_RR tasks by descending priority. They always run
+    /// before anything in `scheduler`.
+    rt_queue: BTreeMap<(Reverse<usize>, usize), Arc<TaskControlBlock>>,
+    rt_seq: usize,
+}
+
+impl TaskManager {
+    pub fn new() -> Self {
+        Self {
+            scheduler: SchedulerImpl::new(),
+            rt_queue: BTreeMap::new(),
+            rt_seq: 0,
+        }
+    }
+
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let inner = task.inner_exclusive_access();
+        let (policy, rt_priority) = (inner.sched.policy, inner.sched.rt_priority);
+        drop(inner);
+
+        if policy.is_realtime() {
+            self.rt_seq += 1;
+            self.rt_queue.insert((Reverse(rt_priority), self.rt_seq), task);
+        } else {
+            self.scheduler.add(task);
+        }
+    }
+
+    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.rt_queue
+            .pop_first()
+            .map(|(_, task)| task)
+            .or_else(|| self.scheduler.fetch())
+    }
+
+    pub fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let inner = task.inner_exclusive_access();
+        let (policy, rt_priority) = (inner.sched.policy, inner.sched.rt_priority);
+        drop(inner);
+
+        let top_rt_priority = self.rt_queue.first_key_value().map(|(&(Reverse(priority), _), _)| priority);
+        match policy {
+            SchedPolicy::Fifo => false,
+            SchedPolicy::RoundRobin => top_rt_priority.is_some_and(|priority| priority >= rt_priority),
+            _ => top_rt_priority.is_some() || self.scheduler.tick(task),
+        }
+    }
+
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let key = self.rt_queue
+            .iter()
+            .find(|(_, t)| Arc::ptr_eq(t, task))
+            .map(|(&key, _)| key);
+        match key {
+            Some(key) => self.rt_queue.remove(&key).is_some(),
+            None => self.scheduler.remove(task),
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
+        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
+
+    /// Every live thread by tid; a main thread's tid is its pid.
+    static ref PID2TASK: UPIntrFreeCell<BTreeMap<usize, Weak<TaskControlBlock>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+
+    /// Every process that has not been reaped yet, by pid.
+    static ref PID2PROCESS: UPIntrFreeCell<BTreeMap<usize, Weak<ProcessControlBlock>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+pub fn add_task(task: Arc<TaskControlBlock>) {
+    TASK_MANAGER.exclusive_access().add(task);
+}
+
+pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.exclusive_access().fetch()
+}
+
+pub fn tick_task(task: &Arc<TaskControlBlock>) -> bool {
+    TASK_MANAGER.exclusive_access().tick(task)
+}
+
+pub fn remove_task(task: &Arc<TaskControlBlock>) -> bool {
+    TASK_MANAGER.exclusive_access().remove(task)
+}
+
+pub fn insert_into_pid2task(task: &Arc<TaskControlBlock>) {
+    PID2TASK.exclusive_access().insert(task.gettid(), Arc::downgrade(task));
+}
+
+pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    PID2TASK.exclusive_access().get(&pid).and_then(Weak::upgrade)
+}
+
+pub fn remove_from_pid2task(pid: usize) {
+    PID2TASK.exclusive_access().remove(&pid);
+    PID2PROCESS.exclusive_access().remove(&pid);
+}
+
+pub fn insert_into_pid2process(process: &Arc<ProcessControlBlock>) {
+    PID2PROCESS.exclusive_access().insert(process.getpid(), Arc::downgrade(process));
+}
+
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PROCESS.exclusive_access().get(&pid).and_then(Weak::upgrade)
+}
+
+/// All processes, in pid order.
+pub fn processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PROCESS.exclusive_access().values().filter_map(Weak::upgrade).collect()
+}
@@ -0,0 +1,392 @@
+use alloc::{string::String, sync::{Arc, Weak}, vec::Vec};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+
+use crate::{config::CLOCK_FREQ, drivers::get_time, fs::{open_file, File, OpenFlags}, loader::{get_app_data, get_app_name, get_num_app, list_apps}, mm::{frames_wanted, translate_user_addr, MemorySet, UserPtr, VirtAddr, VirtPageNum}, sbi::shutdown, sync::UPIntrFreeCell};
+
+use self::{context::TaskContext, manager::tick_task, processor::schedule, user_stack::init_user_stack};
+
+pub use futex::{futex_requeue, futex_wait, futex_waiters_on, futex_wake};
+pub use kernel_stack::{kernel_stack_guard_owner, KERNEL_STACK_PAGES, KERNEL_STACK_SLOT_BITS};
+pub use manager::{add_task, pid2process, pid2task, processes, remove_task};
+pub use scheduler::{SchedPolicy, NICE_MAX, NICE_MIN};
+pub use signal::{
+    current_interrupted, handle_current_signals, send_fault_signal_to_current, send_signal_to_process, send_signal_to_task,
+    sigreturn_current, SigAction, SigSet, SignalSource, NSIG, SIGBUS, SIGILL, SIGKILL, SIGSEGV, SIGSTOP, SIGTRAP, SIG_IGN,
+};
+pub use process::ProcessControlBlock;
+pub use processor::{current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token, run_tasks, take_current_task};
+pub use task::{TaskControlBlock, TaskStats, TaskStatus};
+pub use timer::check_timer;
+
+mod context;
+mod futex;
+mod kernel_stack;
+mod manager;
+mod pid;
+mod process;
+mod processor;
+mod recycle_allocator;
+mod scheduler;
+mod signal;
+mod switch;
+#[allow(clippy::module_inception)]
+mod task;
+mod timer;
+mod user_stack;
+
+const INITPROC_NAME: &str = "initproc";
+
+lazy_static! {
+    /// Adopts orphaned tasks. Only exists if an app named `initproc` is linked.
+    static ref INITPROC: UPIntrFreeCell<Option<Arc<ProcessControlBlock>>> =
+        unsafe { UPIntrFreeCell::new(None) };
+
+    /// Where the swap clock stopped: a pid and a page in its space.
+    static ref CLOCK_HAND: UPIntrFreeCell<(usize, VirtPageNum)> =
+        unsafe { UPIntrFreeCell::new((0, VirtPageNum(0))) };
+}
+
+fn new_app_process(app_id: usize) -> Option<Arc<ProcessControlBlock>> {
+    let page_cache = open_file(get_app_name(app_id), OpenFlags::RDONLY).and_then(|inode| inode.page_cache());
+    let (mut memory_set, elf_info) = match MemorySet::from_elf(get_app_data(app_id), page_cache.as_ref()) {
+        Ok(loaded) => loaded,
+        Err(errno) => {
+            warn!("app {} cannot be loaded ({}), skipped", app_id, errno);
+            return None;
+        }
+    };
+    let argv = [String::from(get_app_name(app_id))];
+    let user_sp = init_user_stack(&mut memory_set, &elf_info, &argv, &[]).unwrap();
+    Some(ProcessControlBlock::new(memory_set, elf_info.entry, user_sp).unwrap())
+}
+
+/// Starts `initproc` if it is linked in, which is then responsible for
+/// running everything else. Otherwise every linked app is started.
+pub fn add_apps() {
+    list_apps();
+
+    if let Some(app_id) = (0..get_num_app()).find(|&app_id| get_app_name(app_id) == INITPROC_NAME) {
+        if let Some(initproc) = new_app_process(app_id) {
+            info!("start {} as pid {}", INITPROC_NAME, initproc.getpid());
+            *INITPROC.exclusive_access() = Some(initproc.clone());
+            add_task(initproc.inner_exclusive_access().threads[0].clone());
+            return;
+        }
+    }
+
+    for app_id in 0..get_num_app() {
+        if let Some(process) = new_app_process(app_id) {
+            add_task(process.inner_exclusive_access().threads[0].clone());
+        }
+    }
+}
+
+fn switch_out_current_and_run_next(status: TaskStatus, voluntary: bool) {
+    let task = take_current_task().unwrap();
+
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    task_inner.task_status = status;
+    let now = get_time();
+    task_inner.sched.update(now);
+    task_inner.stats.charge_kernel(now);
+    if voluntary {
+        task_inner.stats.voluntary_switches += 1;
+    } else {
+        task_inner.stats.involuntary_switches += 1;
+    }
+    drop(task_inner);
+
+    // A blocked task is only referenced by whatever it waits on, which
+    // hands it back to the scheduler through `wakeup_task`.
+    if status == TaskStatus::Ready {
+        add_task(task);
+    }
+    schedule(task_cx_ptr);
+}
+
+/// Gives up the CPU on the current task's own behalf, e.g. `sched_yield`.
+pub fn suspend_current_and_run_next() {
+    switch_out_current_and_run_next(TaskStatus::Ready, true);
+}
+
+/// Takes the CPU away from the current task, e.g. when its time slice ends.
+pub fn preempt_current_and_run_next() {
+    switch_out_current_and_run_next(TaskStatus::Ready, false);
+}
+
+/// Puts the current task to sleep until someone calls `wakeup_task` on it.
+/// The caller must have made the task reachable for that beforehand.
+pub fn block_current_and_run_next() {
+    switch_out_current_and_run_next(TaskStatus::Blocked, true);
+}
+
+/// Makes a blocked task runnable again. Tasks that are not blocked are left
+/// alone, so a spurious wakeup is harmless.
+pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status != TaskStatus::Blocked {
+        return;
+    }
+    task_inner.task_status = TaskStatus::Ready;
+    drop(task_inner);
+
+    add_task(task);
+}
+
+/// Accounts a timer tick to the current task; returns whether it should be preempted.
+pub fn tick_current() -> bool {
+    tick_task(&current_task().unwrap())
+}
+
+/// Lets the current task's address space resolve a fault on `va`, e.g. by
+/// copying a shared page. Returns `false` if the access is illegal. If
+/// there is no memory left to resolve it, the OOM killer makes some.
+pub fn handle_current_page_fault(va: VirtAddr, is_write: bool) -> bool {
+    let Some(process) = current_process() else {
+        return false;
+    };
+    loop {
+        let result = process.inner_exclusive_access().memory_set.handle_page_fault(va, is_write);
+        match result {
+            Ok(handled) => return handled,
+            Err(_) if !out_of_memory(&process) => return false,
+            Err(_) => {}
+        }
+    }
+}
+
+/// Called when a page fault finds no frame. Swapping pages out comes
+/// first; only if that frees nothing is the process with the largest
+/// resident set, initproc aside, killed, and waited on to give its frames
+/// back. Returns `false` if `current` is the victim or is killed meanwhile,
+/// and should not retry.
+fn out_of_memory(current: &Arc<ProcessControlBlock>) -> bool {
+    if reclaim_frames() {
+        return true;
+    }
+
+    let victim = processes()
+        .into_iter()
+        .filter(|process| !is_initproc(process) && !process.inner_exclusive_access().is_zombie)
+        .max_by_key(|process| process.inner_exclusive_access().memory_set.resident_pages());
+    let Some(victim) = victim else {
+        warn!("out of memory, and no process to kill but pid {}", current.getpid());
+        signal::kill_process(current, SIGKILL);
+        return false;
+    };
+    warn!(
+        "out of memory: killing pid {} with {} resident pages",
+        victim.getpid(),
+        victim.inner_exclusive_access().memory_set.resident_pages()
+    );
+    signal::kill_process(&victim, SIGKILL);
+    if Arc::ptr_eq(&victim, current) {
+        return false;
+    }
+
+    // Its address space goes away with its last thread.
+    while !victim.inner_exclusive_access().is_zombie {
+        if current_killed() {
+            return false;
+        }
+        suspend_current_and_run_next();
+    }
+    true
+}
+
+pub fn charge_current_user_time() {
+    current_task().unwrap().inner_exclusive_access().stats.charge_user(get_time());
+}
+
+pub fn charge_current_kernel_time() {
+    current_task().unwrap().inner_exclusive_access().stats.charge_kernel(get_time());
+}
+
+/// Swaps pages out while free frames are below the low watermark. The
+/// clock hand goes round every process in pid order, and twice round at
+/// most, as the first time may only clear `A` bits. Returns whether any
+/// page was swapped out.
+fn reclaim_frames() -> bool {
+    let mut wanted = frames_wanted();
+    if wanted == 0 {
+        return false;
+    }
+    let first_wanted = wanted;
+
+    let (hand_pid, hand_vpn) = *CLOCK_HAND.exclusive_access();
+    let processes = processes();
+    let first = processes.iter().position(|process| process.getpid() >= hand_pid).unwrap_or(0);
+    let order = processes[first..].iter().chain(&processes[..first]);
+
+    let mut from = hand_vpn;
+    for process in order.clone().chain(order.clone()).chain(order.take(1)) {
+        let stopped = process.inner_exclusive_access().memory_set.sweep(from, &mut wanted);
+        if let Some(vpn) = stopped {
+            *CLOCK_HAND.exclusive_access() = (process.getpid(), vpn);
+            return true;
+        }
+        from = VirtPageNum(0);
+    }
+    if wanted > 0 {
+        debug!("could not swap out enough pages, {} more wanted", wanted);
+    }
+    wanted < first_wanted
+}
+
+/// Whether the current thread has been told to exit by another one.
+pub fn current_killed() -> bool {
+    current_task().unwrap().inner_exclusive_access().killed
+}
+
+/// Work left for the current thread before it goes back to user mode.
+pub fn handle_current_return_to_user() {
+    if current_killed() {
+        exit_current_with_status(0);
+    }
+
+    let task = current_task().unwrap();
+    let set_child_tid = core::mem::take(&mut task.inner_exclusive_access().set_child_tid);
+    if set_child_tid != 0 {
+        let token = task.process.inner_exclusive_access().get_user_token();
+        // Like Linux, a bad pointer here is silently ignored.
+        let _ = UserPtr::new(token, set_child_tid as *mut i32).write(task.gettid() as i32);
+    }
+    drop(task);
+
+    handle_current_signals();
+    reclaim_frames();
+}
+
+/// Wait status of a process that exited with `exit_code`.
+pub(crate) fn exited_status(exit_code: i32) -> i32 {
+    (exit_code & 0xff) << 8
+}
+
+/// Wait status of a process killed by `signum`.
+pub(crate) fn signaled_status(signum: usize, core_dumped: bool) -> i32 {
+    signum as i32 | if core_dumped { 0x80 } else { 0 }
+}
+
+/// Ends the current thread with `exit_code`.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    exit_current_with_status(exited_status(exit_code));
+}
+
+/// Ends the current thread. The last thread out turns the process into a
+/// zombie holding `status`, unless exit_group or a signal already chose
+/// one, and releases its user resources right away. The TCB itself, with
+/// the kernel stack in use here, lives on until the idle loop drops it.
+fn exit_current_with_status(status: i32) -> ! {
+    let task = current_task().unwrap();
+    let process = task.process.clone();
+    debug!("tid {} exited with status {:#x}", task.gettid(), status);
+
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.task_status = TaskStatus::Zombie;
+    task_inner.stats.charge_kernel(get_time());
+    let (trap_cx_slot, stats, clear_child_tid) = (task_inner.trap_cx_slot, task_inner.stats, task_inner.clear_child_tid);
+    drop(task_inner);
+
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.threads.retain(|thread| !Arc::ptr_eq(thread, &task));
+    process_inner.stats.accumulate(&stats);
+    let is_last_thread = process_inner.threads.is_empty();
+    let token = process_inner.get_user_token();
+    drop(process_inner);
+
+    if is_last_thread {
+        exit_process(&process, status);
+    } else {
+        if clear_child_tid != 0 && UserPtr::new(token, clear_child_tid as *mut i32).write(0).is_ok() {
+            // Lets pthread_join see that the thread is gone.
+            if let Ok(key) = translate_user_addr(token, clear_child_tid, false) {
+                futex_wake(key, 1);
+            }
+        }
+        process.inner_exclusive_access().dealloc_trap_cx(trap_cx_slot);
+    }
+    drop(process);
+    drop(task);
+
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
+    unreachable!("zombie thread scheduled again");
+}
+
+/// Ends every thread of the current process with `exit_code`.
+pub fn exit_group_current_and_run_next(exit_code: i32) {
+    {
+        let task = current_task().unwrap();
+        let process = task.process.clone();
+        process.inner_exclusive_access().group_exit_status.get_or_insert(exited_status(exit_code));
+        process.kill_other_threads(&task);
+    }
+    exit_current_and_run_next(exit_code);
+}
+
+pub fn is_initproc(process: &Arc<ProcessControlBlock>) -> bool {
+    INITPROC
+        .exclusive_access()
+        .as_ref()
+        .is_some_and(|initproc| Arc::ptr_eq(initproc, process))
+}
+
+fn exit_process(process: &Arc<ProcessControlBlock>, status: i32) {
+    let status = process.inner_exclusive_access().group_exit_status.unwrap_or(status);
+    if is_initproc(process) {
+        info!("{} exited with status {:#x}, shutting down", INITPROC_NAME, status);
+        shutdown(status != 0);
+    }
+
+    let mut process_inner = process.inner_exclusive_access();
+    match status & 0x7f {
+        0 => info!("pid {} exited with code {}", process.getpid(), status >> 8),
+        signum => info!("pid {} killed by signal {}", process.getpid(), signum),
+    }
+    process_inner.is_zombie = true;
+    process_inner.exit_status = status;
+    let stats = process_inner.stats;
+    info!(
+        "pid {} stats: user {} us, kernel {} us, {} voluntary / {} involuntary switches",
+        process.getpid(),
+        stats.user_time * 1_000_000 / CLOCK_FREQ,
+        stats.kernel_time * 1_000_000 / CLOCK_FREQ,
+        stats.voluntary_switches,
+        stats.involuntary_switches
+    );
+
+    let children = core::mem::take(&mut process_inner.children);
+    let parent = process_inner.parent.as_ref().and_then(Weak::upgrade);
+    let exit_signal = process_inner.exit_signal;
+    process_inner.child_waiters.clear();
+    process_inner.fd_table.clear();
+    process_inner.memory_set.recycle_data_pages();
+    drop(process_inner);
+
+    reparent_orphans(children);
+    if let Some(parent) = parent {
+        if exit_signal != 0 {
+            send_signal_to_process(&parent, exit_signal, SignalSource::Child(process.getpid(), status));
+        }
+        parent.wakeup_child_waiters();
+    }
+}
+
+/// Hands the children of an exiting process over to initproc, or lets them
+/// run parentless if there is none.
+fn reparent_orphans(children: Vec<Arc<ProcessControlBlock>>) {
+    let initproc = INITPROC.exclusive_access().clone();
+
+    for child in children.iter() {
+        child.inner_exclusive_access().parent = initproc.as_ref().map(Arc::downgrade);
+    }
+    if let Some(initproc) = initproc {
+        let has_zombie = children.iter().any(|child| child.inner_exclusive_access().is_zombie);
+        initproc.inner_exclusive_access().children.extend(children);
+        if has_zombie {
+            initproc.wakeup_child_waiters();
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use lazy_static::lazy_static;
+use log::trace;
+
+use crate::sync::UPIntrFreeCell;
+
+use super::{manager::remove_from_pid2task, recycle_allocator::RecycleAllocator};
+
+lazy_static! {
+    /// Pids start at 1, since 0 means "the caller" to most syscalls.
+    static ref PID_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
+        unsafe { UPIntrFreeCell::new(RecycleAllocator::new(1)) };
+}
+
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        trace!("dealloc pid {}", self.0);
+        remove_from_pid2task(self.0);
+        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+pub fn pid_alloc() -> PidHandle {
+    PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
+}
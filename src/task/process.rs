@@ -0,0 +1,327 @@
+use alloc::{string::String, sync::{Arc, Weak}, vec, vec::Vec};
+use log::debug;
+
+use crate::{fs::{File, Stdin, Stdout}, mm::{kernel_satp, MemorySet, PageCache, PhysPageNum, VirtAddr}, sync::{UPIntrFreeCell, UPIntrRefMut}, syscall::errno::EINTR, trap::{trap_handler, TrapContext}};
+
+use super::{current_killed, manager::{insert_into_pid2process, insert_into_pid2task}, pid::{pid_alloc, PidHandle}, recycle_allocator::RecycleAllocator, scheduler::SchedEntity, signal::{SigAction, SigInfo, SigSet, NSIG, SIG_IGN}, suspend_current_and_run_next, task::{map_trap_context, trap_cx_bottom_from_slot, TaskControlBlock, TaskStats}, user_stack::init_user_stack, wakeup_task};
+
+/// What the threads of a process share: the address space, open files and
+/// its place in the process tree.
+pub struct ProcessControlBlock {
+    pub pid: PidHandle,
+    inner: UPIntrFreeCell<ProcessControlBlockInner>,
+}
+
+pub struct ProcessControlBlockInner {
+    pub memory_set: MemorySet,
+    pub fd_table: Vec<Option<Arc<dyn File>>>,
+    pub parent: Option<Weak<ProcessControlBlock>>,
+    pub children: Vec<Arc<ProcessControlBlock>>,
+    /// Threads sleeping in wait4, woken whenever a child exits.
+    pub child_waiters: Vec<Arc<TaskControlBlock>>,
+    /// Live threads. The process becomes a zombie when the last one exits.
+    pub threads: Vec<Arc<TaskControlBlock>>,
+    pub trap_cx_slots: RecycleAllocator,
+    pub is_zombie: bool,
+    /// Encoded the way wait4 reports it.
+    pub exit_status: i32,
+    /// Set by exit_group or a fatal signal; wins over the status of the
+    /// last thread out.
+    pub group_exit_status: Option<i32>,
+    /// Sent to the parent on exit; the low byte of the clone flags.
+    pub exit_signal: usize,
+    pub sig_actions: [SigAction; NSIG],
+    /// Signals sent to the process as a whole, taken by any thread.
+    pub sig_pending: SigSet,
+    /// What each signal in `sig_pending` was sent with.
+    pub sig_info: [SigInfo; NSIG],
+    /// Run time of the threads that have already exited.
+    pub stats: TaskStats,
+    /// Totals of all reaped children, for `RUSAGE_CHILDREN`.
+    pub children_stats: TaskStats,
+}
+
+impl ProcessControlBlockInner {
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.satp()
+    }
+
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = self.fd_table.iter().position(|file| file.is_none()) {
+            fd
+        } else {
+            self.fd_table.push(None);
+            self.fd_table.len() - 1
+        }
+    }
+
+    /// Maps a fresh trap context page for a new thread.
+    pub fn alloc_trap_cx(&mut self) -> Result<(usize, PhysPageNum), isize> {
+        let slot = self.trap_cx_slots.alloc();
+        match map_trap_context(&mut self.memory_set, slot) {
+            Ok(ppn) => Ok((slot, ppn)),
+            Err(errno) => {
+                self.trap_cx_slots.dealloc(slot);
+                Err(errno)
+            }
+        }
+    }
+
+    pub fn dealloc_trap_cx(&mut self, slot: usize) {
+        self.memory_set.remove_area_with_start_vpn(VirtAddr::from(trap_cx_bottom_from_slot(slot)).into());
+        self.trap_cx_slots.dealloc(slot);
+    }
+
+    /// Run time of the whole process, live threads included.
+    pub fn total_stats(&self) -> TaskStats {
+        let mut stats = self.stats;
+        for thread in self.threads.iter() {
+            stats.accumulate(&thread.inner_exclusive_access().stats);
+        }
+        stats
+    }
+}
+
+impl ProcessControlBlock {
+    /// Builds a process around a user address space that already contains
+    /// its program image and user stack, with a main thread ready to enter
+    /// it. The main thread still has to be added to the scheduler.
+    pub fn new(memory_set: MemorySet, entry_point: usize, user_sp: usize) -> Result<Arc<Self>, isize> {
+        let pid = pid_alloc();
+
+        debug!("new process pid = {}, entry = {:#x}, user sp = {:#x}", pid.0, entry_point, user_sp);
+
+        let process = Arc::new(Self {
+            pid,
+            inner: unsafe {
+                UPIntrFreeCell::new(ProcessControlBlockInner {
+                    memory_set,
+                    fd_table: vec![
+                        // 0 -> stdin
+                        Some(Arc::new(Stdin)),
+                        // 1 -> stdout
+                        Some(Arc::new(Stdout)),
+                        // 2 -> stderr
+                        Some(Arc::new(Stdout)),
+                    ],
+                    parent: None,
+                    children: Vec::new(),
+                    child_waiters: Vec::new(),
+                    threads: Vec::new(),
+                    trap_cx_slots: RecycleAllocator::new(0),
+                    is_zombie: false,
+                    exit_status: 0,
+                    group_exit_status: None,
+                    exit_signal: 0,
+                    sig_actions: [SigAction::default(); NSIG],
+                    sig_pending: SigSet::empty(),
+                    sig_info: [SigInfo::default(); NSIG],
+                    stats: TaskStats::default(),
+                    children_stats: TaskStats::default(),
+                })
+            },
+        });
+
+        let (trap_cx_slot, trap_cx_ppn) = process.inner_exclusive_access().alloc_trap_cx()?;
+        let thread = Arc::new(TaskControlBlock::new(
+            process.clone(),
+            None,
+            trap_cx_slot,
+            trap_cx_ppn,
+            SchedEntity::new(),
+            SigSet::empty()
+        )?);
+        *thread.inner_exclusive_access().get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            kernel_satp(),
+            trap_handler as usize,
+            thread.kernel_stack.get_top()
+        );
+
+        insert_into_pid2process(&process);
+        insert_into_pid2task(&thread);
+        process.inner_exclusive_access().threads.push(thread);
+        Ok(process)
+    }
+
+    /// Creates a child process that shares this one's pages copy-on-write.
+    /// Only `thread` is carried over, resuming from its trap context.
+    /// `exit_signal` is sent to this process when the child exits. Fails
+    /// with `-ENOMEM`, leaving no trace of the child.
+    pub fn fork(self: &Arc<Self>, thread: &Arc<TaskControlBlock>, exit_signal: usize) -> Result<Arc<Self>, isize> {
+        let mut parent_inner = self.inner_exclusive_access();
+        let mut memory_set = MemorySet::from_existed_user(&mut parent_inner.memory_set)?;
+
+        // Trap contexts were copied along with the rest; only the forking
+        // thread's one is of any use to the child.
+        let thread_inner = thread.inner_exclusive_access();
+        let (trap_cx_slot, sched, sig_mask) = (thread_inner.trap_cx_slot, thread_inner.sched.fork(), thread_inner.sig_mask);
+        drop(thread_inner);
+        let mut trap_cx_slots = parent_inner.trap_cx_slots.clone();
+        for other in parent_inner.threads.iter().filter(|other| !Arc::ptr_eq(other, thread)) {
+            let slot = other.inner_exclusive_access().trap_cx_slot;
+            memory_set.remove_area_with_start_vpn(VirtAddr::from(trap_cx_bottom_from_slot(slot)).into());
+            trap_cx_slots.dealloc(slot);
+        }
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(trap_cx_bottom_from_slot(trap_cx_slot)).into())
+            .unwrap()
+            .ppn();
+
+        let pid = pid_alloc();
+
+        debug!("fork process pid = {} from pid = {}", pid.0, self.pid.0);
+
+        let child = Arc::new(Self {
+            pid,
+            inner: unsafe {
+                UPIntrFreeCell::new(ProcessControlBlockInner {
+                    memory_set,
+                    fd_table: parent_inner.fd_table.clone(),
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    child_waiters: Vec::new(),
+                    threads: Vec::new(),
+                    trap_cx_slots,
+                    is_zombie: false,
+                    exit_status: 0,
+                    group_exit_status: None,
+                    exit_signal,
+                    sig_actions: parent_inner.sig_actions,
+                    sig_pending: SigSet::empty(),
+                    sig_info: [SigInfo::default(); NSIG],
+                    stats: TaskStats::default(),
+                    children_stats: TaskStats::default(),
+                })
+            },
+        });
+        drop(parent_inner);
+
+        let child_thread = Arc::new(TaskControlBlock::new(child.clone(), None, trap_cx_slot, trap_cx_ppn, sched, sig_mask)?);
+        child_thread.inner_exclusive_access().get_trap_cx().kernel_sp = child_thread.kernel_stack.get_top();
+
+        self.inner_exclusive_access().children.push(child.clone());
+        insert_into_pid2process(&child);
+        insert_into_pid2task(&child_thread);
+        child.inner_exclusive_access().threads.push(child_thread);
+        Ok(child)
+    }
+
+    /// Creates a new thread resuming from `thread`'s trap context. The
+    /// thread still has to be added to the scheduler. Fails with `-ENOMEM`.
+    pub fn new_thread(self: &Arc<Self>, thread: &Arc<TaskControlBlock>) -> Result<Arc<TaskControlBlock>, isize> {
+        let (trap_cx_slot, trap_cx_ppn) = self.inner_exclusive_access().alloc_trap_cx()?;
+        let thread_inner = thread.inner_exclusive_access();
+        let (sched, sig_mask) = (thread_inner.sched.fork(), thread_inner.sig_mask);
+        let trap_cx = thread_inner.get_trap_cx();
+        drop(thread_inner);
+
+        let new_thread = match TaskControlBlock::new(self.clone(), Some(pid_alloc()), trap_cx_slot, trap_cx_ppn, sched, sig_mask) {
+            Ok(new_thread) => Arc::new(new_thread),
+            Err(errno) => {
+                self.inner_exclusive_access().dealloc_trap_cx(trap_cx_slot);
+                return Err(errno);
+            }
+        };
+        let new_trap_cx = new_thread.inner_exclusive_access().get_trap_cx();
+        *new_trap_cx = trap_cx.clone();
+        new_trap_cx.kernel_sp = new_thread.kernel_stack.get_top();
+
+        debug!("new thread tid = {} in pid = {}", new_thread.tid, self.getpid());
+
+        insert_into_pid2task(&new_thread);
+        self.inner_exclusive_access().threads.push(new_thread.clone());
+        Ok(new_thread)
+    }
+
+    /// Replaces the address space with a new program, run by `thread`
+    /// alone; every other thread is killed first. On failure the old
+    /// program is left untouched and a negative errno is returned.
+    pub fn exec(
+        &self,
+        thread: &Arc<TaskControlBlock>,
+        elf_data: &[u8],
+        page_cache: Option<&Arc<PageCache>>,
+        argv: Vec<String>,
+        envp: Vec<String>
+    ) -> isize {
+        let (mut memory_set, elf_info) = match MemorySet::from_elf(elf_data, page_cache) {
+            Ok(loaded) => loaded,
+            Err(errno) => return errno,
+        };
+        let user_sp = match init_user_stack(&mut memory_set, &elf_info, &argv, &envp) {
+            Ok(user_sp) => user_sp,
+            Err(errno) => return errno,
+        };
+        let mut trap_cx_slots = RecycleAllocator::new(0);
+        let trap_cx_slot = trap_cx_slots.alloc();
+        let trap_cx_ppn = match map_trap_context(&mut memory_set, trap_cx_slot) {
+            Ok(trap_cx_ppn) => trap_cx_ppn,
+            Err(errno) => return errno,
+        };
+
+        self.kill_other_threads(thread);
+        while self.inner_exclusive_access().threads.len() > 1 {
+            if current_killed() {
+                return -EINTR;
+            }
+            suspend_current_and_run_next();
+        }
+
+        let mut inner = self.inner_exclusive_access();
+        inner.memory_set = memory_set;
+        inner.trap_cx_slots = trap_cx_slots;
+        // Handlers are gone with the old program; ignored signals stay ignored.
+        for action in inner.sig_actions.iter_mut().filter(|action| action.handler != SIG_IGN) {
+            *action = SigAction::default();
+        }
+        drop(inner);
+
+        let mut thread_inner = thread.inner_exclusive_access();
+        thread_inner.trap_cx_slot = trap_cx_slot;
+        thread_inner.trap_cx_ppn = trap_cx_ppn;
+        *thread_inner.get_trap_cx() = TrapContext::app_init_context(
+            elf_info.entry,
+            user_sp,
+            kernel_satp(),
+            trap_handler as usize,
+            thread.kernel_stack.get_top()
+        );
+
+        debug!("exec pid = {}, entry = {:#x}, user sp = {:#x}", self.pid.0, elf_info.entry, user_sp);
+        0
+    }
+
+    /// Makes every thread but `thread` exit the next time it would return
+    /// to user mode, waking those that are asleep.
+    pub fn kill_other_threads(&self, thread: &Arc<TaskControlBlock>) {
+        let others: Vec<_> = self.inner_exclusive_access()
+            .threads
+            .iter()
+            .filter(|other| !Arc::ptr_eq(other, thread))
+            .cloned()
+            .collect();
+        for other in others {
+            other.inner_exclusive_access().killed = true;
+            wakeup_task(other);
+        }
+    }
+
+    /// Wakes every thread of this process sleeping in wait4.
+    pub fn wakeup_child_waiters(&self) {
+        let waiters = core::mem::take(&mut self.inner_exclusive_access().child_waiters);
+        for waiter in waiters {
+            wakeup_task(waiter);
+        }
+    }
+
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+
+    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+}
+
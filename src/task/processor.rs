@@ -0,0 +1,99 @@
+use alloc::sync::Arc;
+use lazy_static::lazy_static;
+use log::{debug, trace};
+
+use crate::{drivers::get_time, sync::UPIntrFreeCell, trap::TrapContext};
+
+use super::{context::TaskContext, manager::fetch_task, timer::check_timer, process::ProcessControlBlock, switch::__switch, task::{TaskControlBlock, TaskStatus}};
+
+pub struct Processor {
+    current: Option<Arc<TaskControlBlock>>,
+    idle_task_cx: TaskContext,
+}
+
+impl Processor {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            idle_task_cx: TaskContext::zero_init(),
+        }
+    }
+
+    fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
+        &mut self.idle_task_cx as *mut _
+    }
+
+    pub fn take_current(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.current.take()
+    }
+
+    pub fn current(&self) -> Option<Arc<TaskControlBlock>> {
+        self.current.as_ref().map(Arc::clone)
+    }
+}
+
+lazy_static! {
+    pub static ref PROCESSOR: UPIntrFreeCell<Processor> =
+        unsafe { UPIntrFreeCell::new(Processor::new()) };
+}
+
+/// The idle loop: keeps picking ready tasks and switching into them. Every
+/// task switches back here through `schedule` when it gives up the CPU.
+pub fn run_tasks() -> ! {
+    loop {
+        let mut processor = PROCESSOR.exclusive_access();
+        if let Some(task) = processor.take_current() {
+            debug!("release exited task, remaining references {}", Arc::strong_count(&task));
+        }
+        if let Some(task) = fetch_task() {
+            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            let mut task_inner = task.inner_exclusive_access();
+            let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
+            task_inner.task_status = TaskStatus::Running;
+            let now = get_time();
+            task_inner.sched.switch_in(now);
+            task_inner.stats.reset_timestamp(now);
+            drop(task_inner);
+            processor.current = Some(task);
+            drop(processor);
+
+            trace!("switch to next task");
+            unsafe { __switch(idle_task_cx_ptr, next_task_cx_ptr) };
+        } else {
+            drop(processor);
+            // Interrupts are off here, so sleepers are woken by polling.
+            check_timer();
+        }
+    }
+}
+
+pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.exclusive_access().take_current()
+}
+
+pub fn current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.exclusive_access().current()
+}
+
+pub fn current_process() -> Option<Arc<ProcessControlBlock>> {
+    current_task().map(|task| task.process.clone())
+}
+
+pub fn current_user_token() -> usize {
+    current_process().unwrap().inner_exclusive_access().get_user_token()
+}
+
+pub fn current_trap_cx() -> &'static mut TrapContext {
+    current_task().unwrap().inner_exclusive_access().get_trap_cx()
+}
+
+pub fn current_trap_cx_user_va() -> usize {
+    current_task().unwrap().inner_exclusive_access().get_trap_cx_user_va()
+}
+
+pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
+    let idle_task_cx_ptr = PROCESSOR.exclusive_session(|processor| processor.get_idle_task_cx_ptr());
+
+    trace!("switch back to idle");
+    unsafe { __switch(switched_task_cx_ptr, idle_task_cx_ptr) };
+}
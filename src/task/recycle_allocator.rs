@@ -0,0 +1,32 @@
+use alloc::vec::Vec;
+
+/// Hands out small integer ids, reusing released ones before growing.
+#[derive(Clone)]
+pub struct RecycleAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl RecycleAllocator {
+    pub const fn new(start: usize) -> Self {
+        Self {
+            current: start,
+            recycled: Vec::new(),
+        }
+    }
+
+    pub fn alloc(&mut self) -> usize {
+        if let Some(id) = self.recycled.pop() {
+            id
+        } else {
+            self.current += 1;
+            self.current - 1
+        }
+    }
+
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current, "id {} has not been allocated", id);
+        assert!(!self.recycled.contains(&id), "id {} has been deallocated", id);
+        self.recycled.push(id);
+    }
+}
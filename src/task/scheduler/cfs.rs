@@ -0,0 +1,64 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+
+use crate::{config::CLOCK_FREQ, drivers::get_time, task::task::TaskControlBlock};
+
+use super::Scheduler;
+
+/// How far the running task may get ahead of the leftmost one, in vruntime.
+const WAKEUP_GRANULARITY: u64 = (CLOCK_FREQ / 1000) as u64;
+
+/// CFS-like scheduler: always runs the task with the smallest weighted
+/// run time, kept ordered in a BTreeMap instead of a red-black tree.
+pub struct CfsScheduler {
+    /// Keyed by `(vruntime, seq)`; `seq` keeps equal vruntimes in FIFO order.
+    ready_queue: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    seq: usize,
+    min_vruntime: u64,
+}
+
+impl Scheduler for CfsScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            seq: 0,
+            min_vruntime: 0,
+        }
+    }
+
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.inner_exclusive_access();
+        // A task coming back from sleep must not make up for lost time.
+        inner.sched.vruntime = inner.sched.vruntime.max(self.min_vruntime);
+        let vruntime = inner.sched.vruntime;
+        drop(inner);
+
+        self.seq += 1;
+        self.ready_queue.insert((vruntime, self.seq), task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((vruntime, _), task) = self.ready_queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(task)
+    }
+
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = task.inner_exclusive_access();
+        inner.sched.update(get_time());
+        let vruntime = inner.sched.vruntime;
+        drop(inner);
+
+        match self.ready_queue.first_key_value() {
+            Some((&(leftmost, _), _)) => vruntime > leftmost + WAKEUP_GRANULARITY,
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let key = self.ready_queue
+            .iter()
+            .find(|(_, t)| Arc::ptr_eq(t, task))
+            .map(|(&key, _)| key);
+        key.and_then(|key| self.ready_queue.remove(&key)).is_some()
+    }
+}
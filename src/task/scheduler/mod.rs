@@ -0,0 +1,139 @@
+use alloc::sync::Arc;
+
+use super::task::TaskControlBlock;
+
+#[cfg(feature = "sched-cfs")]
+mod cfs;
+#[cfg(not(any(feature = "sched-stride", feature = "sched-cfs")))]
+mod rr;
+#[cfg(all(feature = "sched-stride", not(feature = "sched-cfs")))]
+mod stride;
+
+pub trait Scheduler {
+    fn new() -> Self;
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Accounts a timer tick to the running `task` and returns whether it
+    /// should give up the CPU.
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    /// Takes `task` out of the ready queue, returning whether it was queued.
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+}
+
+#[cfg(feature = "sched-cfs")]
+pub use cfs::CfsScheduler as SchedulerImpl;
+
+#[cfg(all(feature = "sched-stride", not(feature = "sched-cfs")))]
+pub use stride::StrideScheduler as SchedulerImpl;
+
+#[cfg(not(any(feature = "sched-stride", feature = "sched-cfs")))]
+pub use rr::RoundRobinScheduler as SchedulerImpl;
+
+pub const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+const NICE_0_WEIGHT: u64 = 1024;
+const IDLE_WEIGHT: u64 = 3;
+
+/// Load weight of each nice level, the same table Linux uses.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SchedPolicy {
+    Normal,
+    Fifo,
+    RoundRobin,
+    Batch,
+    Idle,
+}
+
+impl SchedPolicy {
+    pub fn from_linux(policy: usize) -> Option<Self> {
+        match policy {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Fifo),
+            2 => Some(Self::RoundRobin),
+            3 => Some(Self::Batch),
+            5 => Some(Self::Idle),
+            _ => None,
+        }
+    }
+
+    pub fn to_linux(self) -> usize {
+        match self {
+            Self::Normal => 0,
+            Self::Fifo => 1,
+            Self::RoundRobin => 2,
+            Self::Batch => 3,
+            Self::Idle => 5,
+        }
+    }
+
+    pub fn is_realtime(self) -> bool {
+        matches!(self, Self::Fifo | Self::RoundRobin)
+    }
+}
+
+/// Per-task scheduling parameters and the bookkeeping every policy needs.
+pub struct SchedEntity {
+    pub policy: SchedPolicy,
+    pub nice: isize,
+    pub rt_priority: usize,
+    /// Weighted run time, in clock cycles scaled to nice 0.
+    pub vruntime: u64,
+    /// Stride scheduling pass value.
+    pub pass: u64,
+    exec_start: usize,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            nice: 0,
+            rt_priority: 0,
+            vruntime: 0,
+            pass: 0,
+            exec_start: 0,
+        }
+    }
+
+    /// Scheduling state of a forked child: same policy and priority, and
+    /// the parent's virtual time so it can not jump the queue.
+    pub fn fork(&self) -> Self {
+        Self {
+            policy: self.policy,
+            nice: self.nice,
+            rt_priority: self.rt_priority,
+            vruntime: self.vruntime,
+            pass: self.pass,
+            exec_start: 0,
+        }
+    }
+
+    pub fn weight(&self) -> u64 {
+        match self.policy {
+            SchedPolicy::Idle => IDLE_WEIGHT,
+            _ => NICE_TO_WEIGHT[(self.nice - NICE_MIN) as usize],
+        }
+    }
+
+    pub fn switch_in(&mut self, now: usize) {
+        self.exec_start = now;
+    }
+
+    /// Charges the time run since the last call (or `switch_in`) to `vruntime`.
+    pub fn update(&mut self, now: usize) {
+        let delta = now.saturating_sub(self.exec_start) as u64;
+        self.vruntime += delta * NICE_0_WEIGHT / self.weight();
+        self.exec_start = now;
+    }
+}
@@ -0,0 +1,39 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use crate::task::task::TaskControlBlock;
+
+use super::Scheduler;
+
+/// Plain FIFO ready queue; the running task is preempted on every tick.
+pub struct RoundRobinScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        true
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        if let Some(idx) = self.ready_queue.iter().position(|t| Arc::ptr_eq(t, task)) {
+            self.ready_queue.remove(idx);
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+
+use crate::task::task::TaskControlBlock;
+
+use super::Scheduler;
+
+const BIG_STRIDE: u64 = 1 << 32;
+
+/// Stride scheduling: every quantum goes to the task with the smallest pass,
+/// which then advances by a stride inversely proportional to its weight.
+pub struct StrideScheduler {
+    /// Keyed by `(pass, seq)`; `seq` keeps equal passes in FIFO order.
+    ready_queue: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    seq: usize,
+    min_pass: u64,
+}
+
+impl Scheduler for StrideScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            seq: 0,
+            min_pass: 0,
+        }
+    }
+
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.inner_exclusive_access();
+        // A task coming back from sleep must not make up for lost time.
+        inner.sched.pass = inner.sched.pass.max(self.min_pass);
+        let pass = inner.sched.pass;
+        drop(inner);
+
+        self.seq += 1;
+        self.ready_queue.insert((pass, self.seq), task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((pass, _), task) = self.ready_queue.pop_first()?;
+        self.min_pass = pass;
+
+        let mut inner = task.inner_exclusive_access();
+        inner.sched.pass += BIG_STRIDE / inner.sched.weight();
+        drop(inner);
+
+        Some(task)
+    }
+
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        true
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let key = self.ready_queue
+            .iter()
+            .find(|(_, t)| Arc::ptr_eq(t, task))
+            .map(|(&key, _)| key);
+        key.and_then(|key| self.ready_queue.remove(&key)).is_some()
+    }
+}
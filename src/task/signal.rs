@@ -0,0 +1,448 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{arch::global_asm, mem::{offset_of, size_of}, ops::{BitAnd, BitOr, Not}};
+
+use log::debug;
+
+use crate::{config::SIGRETURN_TRAMPOLINE, mm::UserPtr};
+
+use super::{current_task, exit_current_with_status, process::ProcessControlBlock, signaled_status, task::TaskControlBlock, wakeup_task};
+
+global_asm!(include_str!("sigreturn.S"));
+
+pub const NSIG: usize = 64;
+
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGWINCH: usize = 28;
+pub const SIGSYS: usize = 31;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SA_SIGINFO: usize = 0x4;
+pub const SA_NODEFER: usize = 0x40000000;
+pub const SA_RESETHAND: usize = 0x80000000;
+
+/// `si_code` of a signal sent by kill or tgkill.
+const SI_USER: i32 = 0;
+const SI_TKILL: i32 = -6;
+/// `si_code` of a signal raised by a fault; the precise kind is not tracked.
+const SI_KERNEL: i32 = 0x80;
+/// `si_code` of the signal a parent gets when a child exits or is killed.
+const CLD_EXITED: i32 = 1;
+const CLD_KILLED: i32 = 2;
+const CLD_DUMPED: i32 = 3;
+
+/// `ss_flags` reporting that no alternate signal stack is set up.
+const SS_DISABLE: i32 = 2;
+
+/// A set of signals, bit `signum - 1` standing for `signum`.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_signum(signum: usize) -> Self {
+        Self(1 << (signum - 1))
+    }
+
+    /// Signals that can be neither blocked nor caught.
+    pub const fn unblockable() -> Self {
+        Self(Self::from_signum(SIGKILL).0 | Self::from_signum(SIGSTOP).0)
+    }
+
+    pub fn contains(self, signum: usize) -> bool {
+        self.0 & Self::from_signum(signum).0 != 0
+    }
+
+    pub fn insert(&mut self, signum: usize) {
+        self.0 |= Self::from_signum(signum).0;
+    }
+
+    pub fn remove(&mut self, signum: usize) {
+        self.0 &= !Self::from_signum(signum).0;
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The lowest numbered signal in the set.
+    pub fn first(self) -> Option<usize> {
+        (!self.is_empty()).then(|| self.0.trailing_zeros() as usize + 1)
+    }
+}
+
+impl BitOr for SigSet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for SigSet {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for SigSet {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+/// The kernel's `struct sigaction` on riscv64, which has no `sa_restorer`.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: SigSet,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum DefaultAction {
+    Terminate,
+    CoreDump,
+    Ignore,
+}
+
+/// Stopping is not supported, so the job control signals are ignored.
+fn default_action(signum: usize) -> DefaultAction {
+    match signum {
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Ignore,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ | SIGSYS => DefaultAction::CoreDump,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+impl SigAction {
+    /// Whether delivering `signum` under this action would do nothing.
+    fn ignores(&self, signum: usize) -> bool {
+        match self.handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signum) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// Whether delivering `signum` under this action kills the process.
+    fn is_fatal(&self, signum: usize) -> bool {
+        self.handler == SIG_DFL && default_action(signum) != DefaultAction::Ignore
+    }
+}
+
+/// Wait status of a process killed by `signum`.
+fn fatal_status(signum: usize) -> i32 {
+    signaled_status(signum, default_action(signum) == DefaultAction::CoreDump)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    /// The sender's pid for user signals, the fault address otherwise.
+    addr_or_pid: usize,
+    /// `si_status` for a child's exit, then padding.
+    status: usize,
+    _fields: [usize; 12],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SignalStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// `mcontext_t`: pc followed by x1 to x31, then room for the FP state.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct MContext {
+    gregs: [usize; 32],
+    fpregs: [u64; 66],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    sigmask: SigSet,
+    /// Pads `sigmask` out to the 1024 bits libc reserves for it.
+    _unused: [u8; 120],
+    mcontext: MContext,
+}
+
+/// What a handler finds on its stack, laid out like Linux's `rt_sigframe`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    info: SigInfo,
+    ucontext: UContext,
+}
+
+/// Where a pending signal came from, for `siginfo_t`.
+#[derive(Clone, Copy)]
+pub enum SignalSource {
+    Kill(usize),
+    Tkill(usize),
+    Fault(usize),
+    /// A child's pid and wait status.
+    Child(usize, i32),
+}
+
+impl SignalSource {
+    fn siginfo(self, signum: usize) -> SigInfo {
+        let (code, addr_or_pid, status) = match self {
+            SignalSource::Kill(pid) => (SI_USER, pid, 0),
+            SignalSource::Tkill(pid) => (SI_TKILL, pid, 0),
+            SignalSource::Fault(addr) => (SI_KERNEL, addr, 0),
+            SignalSource::Child(pid, status) => match status & 0x7f {
+                0 => (CLD_EXITED, pid, (status >> 8) & 0xff),
+                signum if status & 0x80 != 0 => (CLD_DUMPED, pid, signum),
+                signum => (CLD_KILLED, pid, signum),
+            },
+        };
+        SigInfo { signo: signum as i32, code, addr_or_pid, status: status as usize, ..Default::default() }
+    }
+}
+
+/// Makes every thread of `process` exit with the status of a death by
+/// `signum`, the current one included.
+pub fn kill_process(process: &Arc<ProcessControlBlock>, signum: usize) {
+    debug!("pid {} killed by signal {}", process.getpid(), signum);
+    process.inner_exclusive_access().group_exit_status.get_or_insert(fatal_status(signum));
+    let threads = process.inner_exclusive_access().threads.clone();
+    for thread in threads {
+        thread.inner_exclusive_access().killed = true;
+        wakeup_task(thread);
+    }
+}
+
+/// Sends `signum` to a process, as kill does. Ignored signals are dropped
+/// right away; fatal ones kill the process as soon as any thread could
+/// take them, without waiting for it to reach user mode.
+pub fn send_signal_to_process(process: &Arc<ProcessControlBlock>, signum: usize, source: SignalSource) {
+    let mut inner = process.inner_exclusive_access();
+    if inner.is_zombie {
+        return;
+    }
+    let action = inner.sig_actions[signum - 1];
+    if signum != SIGKILL && action.ignores(signum) {
+        return;
+    }
+    let receivers: Vec<_> = inner.threads
+        .iter()
+        .filter(|thread| !thread.inner_exclusive_access().sig_mask.contains(signum))
+        .cloned()
+        .collect();
+    if action.is_fatal(signum) && !receivers.is_empty() {
+        drop(inner);
+        kill_process(process, signum);
+        return;
+    }
+    inner.sig_pending.insert(signum);
+    inner.sig_info[signum - 1] = source.siginfo(signum);
+    drop(inner);
+
+    // Get sleepers back to the point where the signal is delivered.
+    for thread in receivers {
+        wakeup_task(thread);
+    }
+}
+
+/// Sends `signum` to one thread, as tgkill does.
+pub fn send_signal_to_task(task: &Arc<TaskControlBlock>, signum: usize, source: SignalSource) {
+    let process = task.process.clone();
+    let process_inner = process.inner_exclusive_access();
+    if process_inner.is_zombie {
+        return;
+    }
+    let action = process_inner.sig_actions[signum - 1];
+    if signum != SIGKILL && action.ignores(signum) {
+        return;
+    }
+    drop(process_inner);
+
+    let mut task_inner = task.inner_exclusive_access();
+    let blocked = task_inner.sig_mask.contains(signum);
+    if action.is_fatal(signum) && !blocked {
+        drop(task_inner);
+        kill_process(&process, signum);
+        return;
+    }
+    task_inner.sig_pending.insert(signum);
+    task_inner.sig_info[signum - 1] = source.siginfo(signum);
+    drop(task_inner);
+
+    if !blocked {
+        wakeup_task(task.clone());
+    }
+}
+
+/// Raises a fault signal like SIGSEGV on the current thread. It cannot be
+/// ignored or blocked: unless a handler is ready to take it, the process is
+/// killed, since the faulting instruction would just fault again.
+pub fn send_fault_signal_to_current(signum: usize, addr: usize) {
+    let task = current_task().unwrap();
+    let process = task.process.clone();
+    let action = process.inner_exclusive_access().sig_actions[signum - 1];
+
+    let mut task_inner = task.inner_exclusive_access();
+    if action.handler == SIG_DFL || action.handler == SIG_IGN || task_inner.sig_mask.contains(signum) {
+        drop(task_inner);
+        kill_process(&process, signum);
+        return;
+    }
+    task_inner.sig_pending.insert(signum);
+    task_inner.sig_info[signum - 1] = SignalSource::Fault(addr).siginfo(signum);
+}
+
+/// Whether the current thread has a signal to take, or is being killed, so
+/// that a blocking syscall should give up with `EINTR`.
+pub fn current_interrupted() -> bool {
+    let task = current_task().unwrap();
+    let process_pending = task.process.inner_exclusive_access().sig_pending;
+    let task_inner = task.inner_exclusive_access();
+    task_inner.killed || !((task_inner.sig_pending | process_pending) & !task_inner.sig_mask).is_empty()
+}
+
+/// Takes the next deliverable signal of the current thread off its pending
+/// sets, thread-directed ones first, with the siginfo it was sent with.
+fn dequeue_signal(task: &Arc<TaskControlBlock>) -> Option<(usize, SigInfo)> {
+    let mut task_inner = task.inner_exclusive_access();
+    let allowed = !task_inner.sig_mask;
+    if let Some(signum) = (task_inner.sig_pending & allowed).first() {
+        task_inner.sig_pending.remove(signum);
+        return Some((signum, task_inner.sig_info[signum - 1]));
+    }
+    drop(task_inner);
+
+    let mut process_inner = task.process.inner_exclusive_access();
+    let signum = (process_inner.sig_pending & allowed).first()?;
+    process_inner.sig_pending.remove(signum);
+    Some((signum, process_inner.sig_info[signum - 1]))
+}
+
+/// Kills the process of the current thread by `signum`, its other threads
+/// included, and ends the current thread right away.
+fn kill_current_process(task: Arc<TaskControlBlock>, signum: usize) -> ! {
+    kill_process(&task.process, signum);
+    drop(task);
+    exit_current_with_status(fatal_status(signum));
+}
+
+/// Delivers a pending signal of the current thread on its way back to user
+/// mode, by pointing the trap context at the handler with a signal frame
+/// pushed on the user stack. Handlers return through `rt_sigreturn`.
+pub fn handle_current_signals() {
+    let task = current_task().unwrap();
+
+    while let Some((signum, info)) = dequeue_signal(&task) {
+        let mut process_inner = task.process.inner_exclusive_access();
+        let action = process_inner.sig_actions[signum - 1];
+        if action.flags & SA_RESETHAND != 0 {
+            process_inner.sig_actions[signum - 1] = SigAction::default();
+        }
+        let token = process_inner.get_user_token();
+        drop(process_inner);
+
+        if action.ignores(signum) {
+            continue;
+        }
+        if action.is_fatal(signum) {
+            // Blocked when it was sent, and unblocked since.
+            kill_current_process(task, signum);
+        }
+
+        let task_inner = task.inner_exclusive_access();
+        let trap_cx = task_inner.get_trap_cx();
+        let mut gregs = trap_cx.gpr;
+        gregs[0] = trap_cx.sepc;
+        let frame = SignalFrame {
+            info,
+            ucontext: UContext {
+                flags: 0,
+                link: 0,
+                stack: SignalStack { sp: 0, flags: SS_DISABLE, size: 0 },
+                sigmask: task_inner.sig_mask,
+                _unused: [0; 120],
+                mcontext: MContext { gregs, fpregs: [0; 66] },
+            },
+        };
+        let frame_ptr = trap_cx.gpr[2].wrapping_sub(size_of::<SignalFrame>()) & !0xf;
+        drop(task_inner);
+
+        if UserPtr::new(token, frame_ptr as *mut SignalFrame).write(frame).is_err() {
+            debug!("tid {} has no room for a signal frame at {:#x}", task.gettid(), frame_ptr);
+            kill_current_process(task, SIGSEGV);
+        }
+
+        let mut task_inner = task.inner_exclusive_access();
+        let trap_cx = task_inner.get_trap_cx();
+        trap_cx.sepc = action.handler;
+        trap_cx.gpr[1] = SIGRETURN_TRAMPOLINE;
+        trap_cx.gpr[2] = frame_ptr;
+        trap_cx.gpr[10] = signum;
+        if action.flags & SA_SIGINFO != 0 {
+            trap_cx.gpr[11] = frame_ptr + offset_of!(SignalFrame, info);
+            trap_cx.gpr[12] = frame_ptr + offset_of!(SignalFrame, ucontext);
+        }
+        task_inner.sig_mask = task_inner.sig_mask | action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            task_inner.sig_mask.insert(signum);
+        }
+        task_inner.sig_mask = task_inner.sig_mask & !SigSet::unblockable();
+
+        debug!("tid {} enters handler {:#x} for signal {}", task.gettid(), action.handler, signum);
+        break;
+    }
+}
+
+/// Restores the context saved by `handle_current_signals` from the frame
+/// at the user sp. Returns the restored a0, which the syscall path then
+/// writes back, or a negative errno if the frame is unreadable.
+pub fn sigreturn_current() -> Result<usize, isize> {
+    let task = current_task().unwrap();
+    let token = task.process.inner_exclusive_access().get_user_token();
+    let frame_ptr = task.inner_exclusive_access().get_trap_cx().gpr[2];
+    let ucontext = UserPtr::new(token, (frame_ptr + offset_of!(SignalFrame, ucontext)) as *mut UContext).read()?;
+
+    let mut task_inner = task.inner_exclusive_access();
+    let trap_cx = task_inner.get_trap_cx();
+    trap_cx.gpr[1..].copy_from_slice(&ucontext.mcontext.gregs[1..]);
+    trap_cx.sepc = ucontext.mcontext.gregs[0];
+    task_inner.sig_mask = ucontext.sigmask & !SigSet::unblockable();
+    Ok(ucontext.mcontext.gregs[10])
+}
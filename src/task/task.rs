@@ -0,0 +1,162 @@
+use alloc::sync::Arc;
+use log::debug;
+
+use crate::{config::{PAGE_SIZE, TRAP_CONTEXT_BASE}, mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr}, sync::{UPIntrFreeCell, UPIntrRefMut}, trap::TrapContext};
+
+use super::{context::TaskContext, kernel_stack::KernelStack, pid::PidHandle, process::ProcessControlBlock, scheduler::SchedEntity, signal::{SigInfo, SigSet, NSIG}};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TaskStatus {
+    Ready,
+    Running,
+    Blocked,
+    Zombie,
+}
+
+/// A thread. Everything it shares with its siblings lives in `process`.
+pub struct TaskControlBlock {
+    pub process: Arc<ProcessControlBlock>,
+    pub tid: usize,
+    /// `None` for the main thread, whose tid is the pid owned by the process.
+    _tid_handle: Option<PidHandle>,
+    pub kernel_stack: KernelStack,
+    inner: UPIntrFreeCell<TaskControlBlockInner>,
+}
+
+pub struct TaskControlBlockInner {
+    /// Which page below `TRAP_CONTEXT_BASE` holds the trap context.
+    pub trap_cx_slot: usize,
+    pub trap_cx_ppn: PhysPageNum,
+    pub task_cx: TaskContext,
+    pub task_status: TaskStatus,
+    pub sched: SchedEntity,
+    pub stats: TaskStats,
+    /// Cleared on exit, for `CLONE_CHILD_CLEARTID` and `set_tid_address`.
+    pub clear_child_tid: usize,
+    /// Written with the tid before first entering user mode, for `CLONE_CHILD_SETTID`.
+    pub set_child_tid: usize,
+    /// Set by another thread's exit_group or exec, or by a fatal signal.
+    /// The thread exits the next time it would return to user mode.
+    pub killed: bool,
+    pub sig_mask: SigSet,
+    /// Signals sent to this thread alone.
+    pub sig_pending: SigSet,
+    /// What each signal in `sig_pending` was sent with.
+    pub sig_info: [SigInfo; NSIG],
+}
+
+/// Run time accounting, in clock cycles, plus context switch counts.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TaskStats {
+    pub user_time: usize,
+    pub kernel_time: usize,
+    pub voluntary_switches: usize,
+    pub involuntary_switches: usize,
+    timestamp: usize,
+}
+
+impl TaskStats {
+    pub fn reset_timestamp(&mut self, now: usize) {
+        self.timestamp = now;
+    }
+
+    pub fn charge_user(&mut self, now: usize) {
+        self.user_time += now.saturating_sub(self.timestamp);
+        self.timestamp = now;
+    }
+
+    pub fn charge_kernel(&mut self, now: usize) {
+        self.kernel_time += now.saturating_sub(self.timestamp);
+        self.timestamp = now;
+    }
+
+    pub fn accumulate(&mut self, other: &TaskStats) {
+        self.user_time += other.user_time;
+        self.kernel_time += other.kernel_time;
+        self.voluntary_switches += other.voluntary_switches;
+        self.involuntary_switches += other.involuntary_switches;
+    }
+}
+
+impl TaskControlBlockInner {
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.trap_cx_ppn.get_mut()
+    }
+
+    pub fn get_trap_cx_user_va(&self) -> usize {
+        trap_cx_bottom_from_slot(self.trap_cx_slot)
+    }
+}
+
+pub fn trap_cx_bottom_from_slot(slot: usize) -> usize {
+    TRAP_CONTEXT_BASE - slot * PAGE_SIZE
+}
+
+/// Maps the trap context page of `slot` into `memory_set`.
+pub fn map_trap_context(memory_set: &mut MemorySet, slot: usize) -> Result<PhysPageNum, isize> {
+    let bottom = trap_cx_bottom_from_slot(slot);
+    memory_set.insert_framed_area(
+        VirtAddr::from(bottom),
+        VirtAddr::from(bottom + PAGE_SIZE),
+        MapPermission::R | MapPermission::W
+    )?;
+    Ok(memory_set
+        .translate(VirtAddr::from(bottom).into())
+        .unwrap()
+        .ppn())
+}
+
+impl TaskControlBlock {
+    /// Creates a thread of `process` that enters user mode through the trap
+    /// context in `trap_cx_slot`, which the caller has to fill in. Fails
+    /// with `-ENOMEM` if there is no memory for its kernel stack.
+    pub fn new(
+        process: Arc<ProcessControlBlock>,
+        tid_handle: Option<PidHandle>,
+        trap_cx_slot: usize,
+        trap_cx_ppn: PhysPageNum,
+        sched: SchedEntity,
+        sig_mask: SigSet
+    ) -> Result<Self, isize> {
+        let tid = tid_handle.as_ref().map_or(process.getpid(), |handle| handle.0);
+        let kernel_stack = KernelStack::new()?;
+        let kernel_stack_top = kernel_stack.get_top();
+
+        debug!("new thread tid = {}, kernel sp = {:#x}", tid, kernel_stack_top);
+
+        Ok(Self {
+            process,
+            tid,
+            _tid_handle: tid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPIntrFreeCell::new(TaskControlBlockInner {
+                    trap_cx_slot,
+                    trap_cx_ppn,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    sched,
+                    stats: TaskStats::default(),
+                    clear_child_tid: 0,
+                    set_child_tid: 0,
+                    killed: false,
+                    sig_mask,
+                    sig_pending: SigSet::empty(),
+                    sig_info: [SigInfo::default(); NSIG],
+                })
+            },
+        })
+    }
+
+    pub fn gettid(&self) -> usize {
+        self.tid
+    }
+
+    pub fn getpid(&self) -> usize {
+        self.process.getpid()
+    }
+
+    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+}
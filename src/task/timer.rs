@@ -0,0 +1,71 @@
+use alloc::{collections::BinaryHeap, sync::Arc, vec::Vec};
+use core::cmp::Ordering;
+
+use lazy_static::lazy_static;
+
+use crate::{drivers::get_time, sync::UPIntrFreeCell};
+
+use super::{task::{TaskControlBlock, TaskStatus}, wakeup_task};
+
+/// A sleeping task and the time, in clock cycles, it should be woken at.
+struct TimerCondVar {
+    expire: usize,
+    task: Arc<TaskControlBlock>,
+}
+
+impl PartialEq for TimerCondVar {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire == other.expire
+    }
+}
+
+impl Eq for TimerCondVar {}
+
+impl PartialOrd for TimerCondVar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed, so that the max-heap yields the earliest timer first.
+impl Ord for TimerCondVar {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.expire.cmp(&self.expire)
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: UPIntrFreeCell<BinaryHeap<TimerCondVar>> =
+        unsafe { UPIntrFreeCell::new(BinaryHeap::new()) };
+}
+
+/// Wakes `task` up through `wakeup_task` once `expire` has passed.
+pub fn add_timer(expire: usize, task: Arc<TaskControlBlock>) {
+    TIMERS.exclusive_access().push(TimerCondVar { expire, task });
+}
+
+/// Cancels every timer of `task`.
+pub fn remove_timer(task: &Arc<TaskControlBlock>) {
+    TIMERS.exclusive_access().retain(|timer| !Arc::ptr_eq(&timer.task, task));
+}
+
+/// Wakes up the tasks whose timers have expired. A task that is still
+/// running has been interrupted on its way to sleep; its timer is kept for
+/// the next check, or the wakeup would be lost.
+pub fn check_timer() {
+    let now = get_time();
+    let mut expired = Vec::new();
+    let mut timers = TIMERS.exclusive_access();
+    while timers.peek().is_some_and(|timer| timer.expire <= now) {
+        expired.push(timers.pop().unwrap());
+    }
+    drop(timers);
+
+    for timer in expired {
+        if timer.task.inner_exclusive_access().task_status == TaskStatus::Running {
+            TIMERS.exclusive_access().push(timer);
+        } else {
+            wakeup_task(timer.task);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{config::{PAGE_SIZE, TICKS_PER_SEC, USER_STACK_SIZE}, drivers::get_time, mm::{ElfInfo, MemorySet}, syscall::errno::{E2BIG, EFAULT}};
+
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_FLAGS: usize = 8;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_HWCAP: usize = 16;
+const AT_CLKTCK: usize = 17;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+
+/// Pushes data downwards onto a user stack that is not necessarily the
+/// current address space.
+struct StackWriter<'a> {
+    memory_set: &'a mut MemorySet,
+    sp: usize,
+    bottom: usize,
+}
+
+impl StackWriter<'_> {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<usize, isize> {
+        if self.sp - self.bottom < bytes.len() {
+            return Err(-E2BIG);
+        }
+        self.sp -= bytes.len();
+        // The new space is not the current one, so write to it directly
+        // rather than through the current task's page faults.
+        self.memory_set.write_bytes(self.sp, bytes).map_err(|errno| if errno == -EFAULT { -E2BIG } else { errno })?;
+        Ok(self.sp)
+    }
+
+    fn push_str(&mut self, s: &str) -> Result<usize, isize> {
+        self.push_bytes(&[0])?;
+        self.push_bytes(s.as_bytes())
+    }
+
+    fn align(&mut self, align: usize) {
+        self.sp &= !(align - 1);
+    }
+}
+
+fn random_bytes() -> [u8; 16] {
+    // xorshift64, seeded from the timer; AT_RANDOM only needs to differ.
+    let mut x = get_time() as u64 | 1;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        chunk.copy_from_slice(&x.to_le_bytes());
+    }
+    bytes
+}
+
+/// Lays out the initial user stack the Linux RISC-V ABI expects: `argc`,
+/// `argv`, `envp` and the auxiliary vector at sp, with the strings they
+/// point to above them. Returns the new sp, 16-byte aligned.
+pub fn init_user_stack(memory_set: &mut MemorySet, elf_info: &ElfInfo, argv: &[String], envp: &[String]) -> Result<usize, isize> {
+    let mut writer = StackWriter {
+        memory_set,
+        sp: elf_info.user_sp,
+        bottom: elf_info.user_sp - USER_STACK_SIZE,
+    };
+
+    let mut envp_ptrs = Vec::new();
+    for env in envp.iter() {
+        envp_ptrs.push(writer.push_str(env)?);
+    }
+    let mut argv_ptrs = Vec::new();
+    for arg in argv.iter() {
+        argv_ptrs.push(writer.push_str(arg)?);
+    }
+    writer.align(16);
+    let random_ptr = writer.push_bytes(&random_bytes())?;
+
+    let auxv = [
+        (AT_PHDR, elf_info.phdr),
+        (AT_PHENT, elf_info.phent),
+        (AT_PHNUM, elf_info.phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, elf_info.entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_HWCAP, 0),
+        (AT_CLKTCK, TICKS_PER_SEC),
+        (AT_SECURE, 0),
+        (AT_RANDOM, random_ptr),
+        (AT_NULL, 0),
+    ];
+
+    let mut table: Vec<usize> = Vec::new();
+    table.push(argv.len());
+    table.extend(argv_ptrs.iter());
+    table.push(0);
+    table.extend(envp_ptrs.iter());
+    table.push(0);
+    for (key, value) in auxv {
+        table.push(key);
+        table.push(value);
+    }
+
+    let table_bytes: Vec<u8> = table.iter().flat_map(|word| word.to_le_bytes()).collect();
+    writer.sp -= table_bytes.len();
+    writer.align(16);
+    writer.sp += table_bytes.len();
+    writer.push_bytes(&table_bytes)
+}
@@ -1,9 +1,9 @@
 use core::arch::asm;
 
-use log::{debug, trace};
-use riscv::register::{scause::{self, Exception, Interrupt, Trap}, sie, sscratch, sstatus, stval, stvec::{self, TrapMode}};
+use log::{debug, trace, warn};
+use riscv::register::{scause::{self, Exception, Interrupt, Trap}, sepc, sie, sscratch, sstatus, stval, stvec::{self, TrapMode}};
 
-use crate::{config::TRAMPOLINE, drivers::set_next_trigger};
+use crate::{config::TRAMPOLINE, drivers::set_next_trigger, syscall::syscall, task::{charge_current_kernel_time, charge_current_user_time, check_timer, current_killed, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token, handle_current_page_fault, handle_current_return_to_user, kernel_stack_guard_owner, preempt_current_and_run_next, send_fault_signal_to_current, tick_current, SIGBUS, SIGILL, SIGSEGV, SIGTRAP}};
 
 extern "C" {
     fn __alltraps();
@@ -16,10 +16,7 @@ pub fn set_kernel_trap_entry() {
     let __alltraps_k_va = __alltraps_k as usize - __alltraps as usize + TRAMPOLINE;
 
     debug!("set kernel trap entry!");
-    unsafe {
-        stvec::write(__alltraps_k_va, TrapMode::Direct);
-        sscratch::write(trap_from_kernel as usize);
-    }
+    unsafe { stvec::write(__alltraps_k_va, TrapMode::Direct) };
 }
 
 fn set_user_trap_entry() {
@@ -46,17 +43,61 @@ fn disable_supervisor_interrupt() {
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    charge_current_user_time();
 
     let scause = scause::read();
     let stval = stval::read();
 
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            let mut cx = current_trap_cx();
+            cx.sepc += 4;
+
             enable_supervisor_interrupt();
+
+            let syscall_id = cx.gpr[17];
+            let args = [cx.gpr[10], cx.gpr[11], cx.gpr[12], cx.gpr[13], cx.gpr[14], cx.gpr[15]];
+            let result = syscall(syscall_id, args);
+
+            // The trap context may have been replaced during the syscall, e.g. by exec.
+            cx = current_trap_cx();
+            cx.gpr[10] = result as usize;
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            let is_write = scause.cause() == Trap::Exception(Exception::StorePageFault);
+            // A thread the OOM killer took down just exits.
+            if !handle_current_page_fault(stval.into(), is_write) && !current_killed() {
+                user_fault(scause.cause(), stval, SIGSEGV);
+            }
+        }
+        Trap::Exception(Exception::IllegalInstruction) => {
+            user_fault(scause.cause(), stval, SIGILL);
+        }
+        Trap::Exception(Exception::InstructionMisaligned)
+        | Trap::Exception(Exception::LoadMisaligned)
+        | Trap::Exception(Exception::StoreMisaligned) => {
+            user_fault(scause.cause(), stval, SIGBUS);
+        }
+        Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault)
+        | Trap::Exception(Exception::StoreFault) => {
+            user_fault(scause.cause(), stval, SIGSEGV);
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            user_fault(scause.cause(), stval, SIGTRAP);
+        }
+        Trap::Exception(_) => {
+            user_fault(scause.cause(), stval, SIGILL);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             debug!("timer interrupt!");
             set_next_trigger();
+            check_timer();
+            if tick_current() {
+                preempt_current_and_run_next();
+            }
         }
         _ => {
             panic!("Unsupported trap {:?} from user, stval = {:#x}!", scause.cause(), stval);
@@ -66,11 +107,31 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// Reports an exception the current thread cannot recover from and raises
+/// `signum` for it. Without a handler only the faulting process dies, with
+/// a wait status naming the signal; the rest of the system carries on.
+fn user_fault(cause: Trap, stval: usize, signum: usize) {
+    let task = current_task().unwrap();
+    let pc = task.inner_exclusive_access().get_trap_cx().sepc;
+    warn!(
+        "tid {} of pid {}: {:?} at pc {:#x}, stval {:#x}, raising signal {}",
+        task.gettid(), task.getpid(), cause, pc, stval, signum
+    );
+    drop(task);
+
+    send_fault_signal_to_current(signum, stval);
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
+    handle_current_return_to_user();
+
     disable_supervisor_interrupt();
     set_user_trap_entry();
+    charge_current_kernel_time();
 
+    let trap_cx_ptr = current_trap_cx_user_va();
+    let user_satp = current_user_token();
     let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
 
     unsafe {
@@ -78,6 +139,8 @@ pub fn trap_return() -> ! {
             "fence.i",
             "jr {restore_va}",
             restore_va = in(reg) restore_va,
+            in("a0") trap_cx_ptr,
+            in("a1") user_satp,
             options(noreturn)
         );
     }
@@ -92,9 +155,27 @@ pub fn trap_from_kernel() {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             debug!("timer interrupt!");
             set_next_trigger();
+            check_timer();
         }
         _ => {
             panic!("Unsupported trap {:?} from kernel, stval = {:#x}", scause.cause(), stval);
         }
     }
 }
+
+/// Entered on the overflow stack when a kernel trap is taken with sp inside
+/// a kernel stack guard; the faulting sp is left in sscratch.
+#[no_mangle]
+pub fn kernel_stack_overflow() -> ! {
+    let sp = sscratch::read();
+    let id = kernel_stack_guard_owner(sp).unwrap();
+
+    panic!(
+        "kernel stack {} overflow, sp = {:#x}, {:?} at sepc = {:#x}, stval = {:#x}",
+        id,
+        sp,
+        scause::read().cause(),
+        sepc::read(),
+        stval::read()
+    );
+}
@@ -2,12 +2,23 @@ use core::arch::global_asm;
 
 use log::info;
 
+use crate::{config::{PAGE_SIZE_BITS, TRAMPOLINE}, task::{KERNEL_STACK_PAGES, KERNEL_STACK_SLOT_BITS}};
+
 use self::handler::set_kernel_trap_entry;
 
+pub use context::TrapContext;
+pub use handler::{enable_timer_interrupt, trap_handler, trap_return};
+
 mod context;
 mod handler;
 
-global_asm!(include_str!("trap.S"));
+global_asm!(
+    include_str!("trap.S"),
+    trampoline = const TRAMPOLINE,
+    slot_bits = const KERNEL_STACK_SLOT_BITS,
+    page_bits = const PAGE_SIZE_BITS,
+    stack_pages = const KERNEL_STACK_PAGES,
+);
 
 pub fn init() {
     info!("Initalizing kernel trap.");
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{brk, exit, fork, waitpid, wifsignaled, wtermsig, PAGE_SIZE, SIGSEGV};
+
+fn byte(addr: usize) -> *mut u8 {
+    addr as *mut u8
+}
+
+/// Whether touching `addr` in a child gets it killed by `SIGSEGV`.
+fn faults(addr: usize) -> bool {
+    let pid = fork();
+    if pid == 0 {
+        unsafe { byte(addr).write_volatile(1) };
+        exit(0);
+    }
+    let (_, status) = waitpid(pid, 0);
+    wifsignaled(status) && wtermsig(status) == SIGSEGV as i32
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    let start = brk(0);
+    assert_ne!(start, 0);
+
+    // Grows to a byte, not a page.
+    let end = start + 3 * PAGE_SIZE + 100;
+    assert_eq!(brk(end), end);
+    assert_eq!(brk(0), end);
+    for addr in (start..end).step_by(PAGE_SIZE).chain([end - 1]) {
+        unsafe {
+            assert_eq!(byte(addr).read_volatile(), 0);
+            byte(addr).write_volatile(0x5a);
+            assert_eq!(byte(addr).read_volatile(), 0x5a);
+        }
+    }
+    assert!(faults(end.next_multiple_of(PAGE_SIZE)));
+
+    // Pages given back come back zeroed.
+    let shrunk = start + PAGE_SIZE;
+    assert_eq!(brk(shrunk), shrunk);
+    assert!(faults(shrunk.next_multiple_of(PAGE_SIZE)));
+    assert_eq!(brk(end), end);
+    assert_eq!(unsafe { byte(end - 1).read_volatile() }, 0);
+
+    // Requests that cannot be met leave the break alone.
+    assert_eq!(brk(start - PAGE_SIZE), end);
+    assert_eq!(brk(usize::MAX), end);
+
+    assert_eq!(brk(start), start);
+    println!("brk: ok");
+    0
+}
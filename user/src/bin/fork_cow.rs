@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+use user_lib::{exit, fork, getpid, getppid, waitpid, wexitstatus, wifexited, yield_, PAGE_SIZE};
+
+const CHILDREN: usize = 8;
+const WORDS: usize = 4 * PAGE_SIZE / 8;
+
+/// A few pages of data, so that each is copied on its own.
+static mut DATA: [usize; WORDS] = [1; WORDS];
+
+fn data(i: usize) -> *mut usize {
+    unsafe { addr_of_mut!(DATA[i]) }
+}
+
+fn check_data(value: usize) {
+    for i in (0..WORDS).step_by(PAGE_SIZE / 8) {
+        assert_eq!(unsafe { read_volatile(data(i)) }, value);
+    }
+}
+
+fn fill_data(value: usize) {
+    for i in (0..WORDS).step_by(PAGE_SIZE / 8) {
+        unsafe { write_volatile(data(i), value) };
+    }
+}
+
+/// The parent writes right after forking; whichever runs first, the child
+/// must still see what was there at the fork.
+fn parent_writes_after_fork() {
+    let parent = getpid();
+    let mut local = 1usize;
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..16 {
+            yield_();
+        }
+        assert_eq!(getppid(), parent);
+        assert_eq!(unsafe { read_volatile(&local) }, 1);
+        check_data(1);
+        exit(0);
+    }
+    assert!(pid > 0);
+    unsafe { write_volatile(&mut local, 2) };
+    fill_data(2);
+
+    let (waited, status) = waitpid(pid, 0);
+    assert_eq!(waited, pid);
+    assert!(wifexited(status) && wexitstatus(status) == 0);
+    fill_data(1);
+}
+
+/// Children scribble over their copies, which the parent must not see.
+fn children_write() {
+    let mut pids = [0; CHILDREN];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        *pid = fork();
+        if *pid == 0 {
+            check_data(1);
+            fill_data(i + 100);
+            yield_();
+            check_data(i + 100);
+            exit(i as i32);
+        }
+        assert!(*pid > 0);
+    }
+    for (i, &pid) in pids.iter().enumerate() {
+        let (waited, status) = waitpid(pid, 0);
+        assert_eq!(waited, pid);
+        assert!(wifexited(status) && wexitstatus(status) == i as i32);
+    }
+    check_data(1);
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    parent_writes_after_fork();
+    children_write();
+    println!("fork_cow: ok");
+    0
+}
@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::{ptr::addr_of_mut, sync::atomic::{AtomicU32, Ordering}};
+
+use user_lib::{futex_cmp_requeue, futex_wait, futex_wake, gettid, join, spawn, yield_, TimeSpec, EAGAIN, ETIMEDOUT};
+
+const THREADS: usize = 4;
+const STACK_SIZE: usize = 4096 * 4;
+const ROUNDS: usize = 1000;
+
+static mut STACKS: [[u8; STACK_SIZE]; THREADS] = [[0; STACK_SIZE]; THREADS];
+
+fn stack_top(i: usize) -> usize {
+    unsafe { addr_of_mut!(STACKS[i]) as usize + STACK_SIZE }
+}
+
+/// A mutex in the style of Drepper's "Futexes Are Tricky": 0 is unlocked,
+/// 1 locked, 2 locked with waiters.
+struct Mutex(AtomicU32);
+
+impl Mutex {
+    fn lock(&self) {
+        if self.0.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            return;
+        }
+        while self.0.swap(2, Ordering::Acquire) != 0 {
+            futex_wait(&self.0, 2, None);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.0.swap(0, Ordering::Release) == 2 {
+            futex_wake(&self.0, 1);
+        }
+    }
+}
+
+static MUTEX: Mutex = Mutex(AtomicU32::new(0));
+static mut COUNTER: usize = 0;
+
+extern "C" fn count(_arg: usize) -> i32 {
+    for _ in 0..ROUNDS {
+        MUTEX.lock();
+        // Not atomic: only the mutex keeps increments from getting lost.
+        unsafe {
+            let counter = addr_of_mut!(COUNTER);
+            let value = counter.read_volatile();
+            if gettid() % 2 == 0 {
+                yield_();
+            }
+            counter.write_volatile(value + 1);
+        }
+        MUTEX.unlock();
+    }
+    0
+}
+
+static FROM: AtomicU32 = AtomicU32::new(0);
+static TO: AtomicU32 = AtomicU32::new(0);
+
+extern "C" fn wait_for_from(_arg: usize) -> i32 {
+    while FROM.load(Ordering::Acquire) == 0 {
+        futex_wait(&FROM, 0, None);
+    }
+    0
+}
+
+fn wait_checks_value_and_times_out() {
+    let futex = AtomicU32::new(1);
+    assert_eq!(futex_wait(&futex, 0, None), -EAGAIN);
+
+    let timeout = TimeSpec { sec: 0, nsec: 10_000_000 };
+    assert_eq!(futex_wait(&futex, 1, Some(&timeout)), -ETIMEDOUT);
+}
+
+fn mutex_keeps_count() {
+    let tids: [AtomicU32; THREADS] = Default::default();
+    for (i, tid) in tids.iter().enumerate() {
+        assert!(spawn(count, 0, stack_top(i), tid) > 0);
+    }
+    for tid in tids.iter() {
+        join(tid);
+    }
+    assert_eq!(unsafe { addr_of_mut!(COUNTER).read_volatile() }, THREADS * ROUNDS);
+}
+
+/// Waiters on `FROM` are moved over to `TO` and woken from there.
+fn requeue_moves_waiters() {
+    let tids: [AtomicU32; THREADS] = Default::default();
+    for (i, tid) in tids.iter().enumerate() {
+        assert!(spawn(wait_for_from, 0, stack_top(i), tid) > 0);
+    }
+    for _ in 0..16 {
+        yield_();
+    }
+
+    assert_eq!(futex_cmp_requeue(&FROM, 0, THREADS, &TO, 1), -EAGAIN);
+    let moved = futex_cmp_requeue(&FROM, 0, THREADS, &TO, 0);
+    assert!(moved >= 0 && moved as usize <= THREADS);
+    FROM.store(1, Ordering::Release);
+    assert_eq!(futex_wake(&TO, THREADS as u32), moved);
+    // Any that were not asleep yet see the new value by themselves.
+    futex_wake(&FROM, THREADS as u32);
+    for tid in tids.iter() {
+        join(tid);
+    }
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    wait_checks_value_and_times_out();
+    mutex_keeps_count();
+    requeue_moves_waiters();
+    println!("futex: ok");
+    0
+}
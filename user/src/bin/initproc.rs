@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{execve, exit, fork, waitpid, wexitstatus, wifexited, wifsignaled, wtermsig, WNOHANG};
+
+/// Run one after another, each in a child of its own, and passed if it
+/// exits with 0. Names end with a NUL so they can go to `execve` as is.
+const TESTS: &[&str] = &[
+    "fork_cow\0",
+    "futex\0",
+    "signals\0",
+    "brk\0",
+    "mmap\0",
+    "mmap_file\0",
+    "swap\0",
+];
+
+/// Runs every test and exits with how many failed, which the kernel turns
+/// into a failed shutdown.
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    let mut failed = 0;
+    for &test in TESTS {
+        let name = test.trim_end_matches('\0');
+        println!("[initproc] running {}", name);
+        let pid = fork();
+        if pid == 0 {
+            execve(test, &[test.as_ptr(), core::ptr::null()]);
+            println!("[initproc] cannot execute {}", name);
+            exit(127);
+        }
+
+        let (_, status) = waitpid(pid, 0);
+        if wifexited(status) && wexitstatus(status) == 0 {
+            println!("[initproc] {} passed", name);
+            continue;
+        }
+        failed += 1;
+        if wifsignaled(status) {
+            println!("[initproc] {} FAILED, killed by signal {}", name, wtermsig(status));
+        } else {
+            println!("[initproc] {} FAILED, exited with {}", name, wexitstatus(status));
+        }
+    }
+
+    // Orphans are ours to reap.
+    while waitpid(-1, WNOHANG).0 > 0 {}
+    println!("[initproc] {} of {} tests passed", TESTS.len() - failed, TESTS.len());
+    failed as i32
+}
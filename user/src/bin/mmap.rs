@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, mmap, mprotect, munmap, waitpid, wexitstatus, wifexited, wifsignaled, wtermsig, EINVAL,
+    MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE, PROT_NONE, PROT_READ, PROT_WRITE, SIGSEGV,
+};
+
+const PAGES: usize = 4;
+
+fn word(addr: usize) -> *mut usize {
+    addr as *mut usize
+}
+
+fn map_anonymous(pages: usize, flags: usize) -> usize {
+    let addr = mmap(0, pages * PAGE_SIZE, PROT_READ | PROT_WRITE, flags | MAP_ANONYMOUS, -1, 0);
+    assert!(addr > 0, "mmap failed with {}", addr);
+    assert!((addr as usize).is_multiple_of(PAGE_SIZE));
+    addr as usize
+}
+
+/// Runs `f` in a child and returns its wait status.
+fn in_child(f: impl FnOnce()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    waitpid(pid, 0).1
+}
+
+fn killed_by_sigsegv(status: i32) -> bool {
+    wifsignaled(status) && wtermsig(status) == SIGSEGV as i32
+}
+
+fn exited_ok(status: i32) -> bool {
+    wifexited(status) && wexitstatus(status) == 0
+}
+
+fn bad_arguments_fail() {
+    assert_eq!(mmap(0, 0, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0), -EINVAL);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, MAP_ANONYMOUS, -1, 0), -EINVAL);
+    assert_eq!(mmap(PAGE_SIZE + 1, PAGE_SIZE, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, -1, 0), -EINVAL);
+}
+
+/// Fresh pages read as zero, and an unmapped hole faults while the pages
+/// around it stay.
+fn private_mapping_and_munmap() {
+    let addr = map_anonymous(PAGES, MAP_PRIVATE);
+    for i in 0..PAGES {
+        let page = addr + i * PAGE_SIZE;
+        unsafe {
+            assert_eq!(word(page).read_volatile(), 0);
+            word(page).write_volatile(i + 1);
+        }
+    }
+
+    let hole = addr + PAGE_SIZE;
+    assert_eq!(munmap(hole, PAGE_SIZE), 0);
+    assert!(killed_by_sigsegv(in_child(|| unsafe { word(hole).write_volatile(0) })));
+    unsafe {
+        assert_eq!(word(addr).read_volatile(), 1);
+        assert_eq!(word(addr + 2 * PAGE_SIZE).read_volatile(), 3);
+    }
+
+    // MAP_FIXED fills the hole again, with zeroes.
+    let fixed = mmap(hole, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, -1, 0);
+    assert_eq!(fixed as usize, hole);
+    assert_eq!(unsafe { word(hole).read_volatile() }, 0);
+
+    assert_eq!(munmap(addr, PAGES * PAGE_SIZE), 0);
+    assert!(killed_by_sigsegv(in_child(|| unsafe { word(addr).write_volatile(0) })));
+}
+
+/// Taking write permission away makes writes fault but reads still work.
+fn mprotect_changes_permissions() {
+    let addr = map_anonymous(PAGES, MAP_PRIVATE);
+    unsafe { word(addr).write_volatile(7) };
+
+    assert_eq!(mprotect(addr, PAGE_SIZE, PROT_READ), 0);
+    assert_eq!(unsafe { word(addr).read_volatile() }, 7);
+    assert!(killed_by_sigsegv(in_child(|| unsafe { word(addr).write_volatile(8) })));
+    // Only the first page changed.
+    unsafe { word(addr + PAGE_SIZE).write_volatile(9) };
+
+    assert_eq!(mprotect(addr + PAGE_SIZE, PAGE_SIZE, PROT_NONE), 0);
+    assert!(killed_by_sigsegv(in_child(|| unsafe {
+        word(addr + PAGE_SIZE).read_volatile();
+    })));
+
+    assert_eq!(mprotect(addr, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE), 0);
+    unsafe {
+        word(addr).write_volatile(8);
+        assert_eq!(word(addr).read_volatile(), 8);
+        assert_eq!(word(addr + PAGE_SIZE).read_volatile(), 9);
+    }
+    assert_eq!(munmap(addr, PAGES * PAGE_SIZE), 0);
+}
+
+/// A shared mapping is one set of pages across fork, even the ones nobody
+/// had touched before; a private one is copied.
+fn shared_and_private_across_fork() {
+    let shared = map_anonymous(PAGES, MAP_SHARED);
+    let private = map_anonymous(PAGES, MAP_PRIVATE);
+    unsafe {
+        word(shared).write_volatile(1);
+        word(private).write_volatile(1);
+    }
+
+    let status = in_child(|| unsafe {
+        assert_eq!(word(shared).read_volatile(), 1);
+        assert_eq!(word(private).read_volatile(), 1);
+        for i in 0..PAGES {
+            word(shared + i * PAGE_SIZE).write_volatile(i + 10);
+            word(private + i * PAGE_SIZE).write_volatile(i + 10);
+        }
+    });
+    assert!(exited_ok(status));
+
+    for i in 0..PAGES {
+        unsafe {
+            assert_eq!(word(shared + i * PAGE_SIZE).read_volatile(), i + 10);
+            assert_eq!(word(private + i * PAGE_SIZE).read_volatile(), if i == 0 { 1 } else { 0 });
+        }
+    }
+    assert_eq!(munmap(shared, PAGES * PAGE_SIZE), 0);
+    assert_eq!(munmap(private, PAGES * PAGE_SIZE), 0);
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    bad_arguments_fail();
+    private_mapping_and_munmap();
+    mprotect_changes_permissions();
+    shared_and_private_across_fork();
+    println!("mmap: ok");
+    0
+}
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, mmap, msync, munmap, open, read, EACCES, EBADF, EOVERFLOW, MAP_PRIVATE, MAP_SHARED, MS_SYNC, O_RDONLY,
+    O_RDWR, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+/// Always there, since the kernel starts it.
+const FILE: &str = "initproc\0";
+/// Written through a shared mapping and restored. No other test runs at
+/// the same time, so nothing else holds its page cache and a fresh one is
+/// read in after the last `munmap`.
+const WRITABLE_FILE: &str = "brk\0";
+
+fn byte(addr: usize) -> *mut u8 {
+    addr as *mut u8
+}
+
+fn map_file(fd: usize, pages: usize, prot: usize, flags: usize, offset: usize) -> usize {
+    let addr = mmap(0, pages * PAGE_SIZE, prot, flags, fd as isize, offset);
+    assert!(addr > 0, "mmap failed with {}", addr);
+    addr as usize
+}
+
+fn read_bytes<const N: usize>(addr: usize) -> [u8; N] {
+    core::array::from_fn(|i| unsafe { byte(addr + i).read_volatile() })
+}
+
+/// The first byte of the second page of `WRITABLE_FILE`, as `read` sees it.
+fn file_byte() -> u8 {
+    let fd = open(WRITABLE_FILE, O_RDONLY);
+    assert!(fd >= 0, "cannot open {}: {}", WRITABLE_FILE.trim_end_matches('\0'), fd);
+    let mut buf = [0; PAGE_SIZE + 1];
+    assert_eq!(read(fd as usize, &mut buf), buf.len() as isize);
+    assert_eq!(close(fd as usize), 0);
+    buf[PAGE_SIZE]
+}
+
+/// Stores through a shared mapping are seen by `read` and reach the file
+/// on `msync` and on the last `munmap`.
+fn write_shared() {
+    let fd = open(WRITABLE_FILE, O_RDWR);
+    assert!(fd >= 0, "cannot open {} for writing: {}", WRITABLE_FILE.trim_end_matches('\0'), fd);
+    let shared = map_file(fd as usize, 1, PROT_READ | PROT_WRITE, MAP_SHARED, PAGE_SIZE);
+    assert_eq!(close(fd as usize), 0);
+
+    let old = file_byte();
+    assert_eq!(unsafe { byte(shared).read_volatile() }, old);
+    unsafe { byte(shared).write_volatile(!old) };
+    assert_eq!(msync(shared, PAGE_SIZE, MS_SYNC), 0);
+    assert_eq!(file_byte(), !old);
+
+    unsafe { byte(shared).write_volatile(old) };
+    assert_eq!(munmap(shared, PAGE_SIZE), 0);
+    assert_eq!(file_byte(), old);
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    let fd = open(FILE, O_RDONLY);
+    assert!(fd >= 0, "cannot open {}: {}", FILE.trim_end_matches('\0'), fd);
+    let fd = fd as usize;
+
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE, 1000, 0), -EBADF);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd as isize, 0), -EACCES);
+    assert_eq!(mmap(0, 2 * PAGE_SIZE, PROT_READ, MAP_PRIVATE, fd as isize, usize::MAX & !(PAGE_SIZE - 1)), -EOVERFLOW);
+
+    let whole = map_file(fd, 2, PROT_READ, MAP_SHARED, 0);
+    assert_eq!(read_bytes::<4>(whole), *b"\x7fELF");
+    assert_eq!(msync(whole, 2 * PAGE_SIZE, MS_SYNC), 0);
+
+    // An offset lands on the same bytes as in the whole mapping.
+    let second = map_file(fd, 1, PROT_READ, MAP_PRIVATE, PAGE_SIZE);
+    assert_eq!(read_bytes::<64>(second), read_bytes::<64>(whole + PAGE_SIZE));
+
+    // Writing a private mapping copies the page, the file stays as it was.
+    let private = map_file(fd, 1, PROT_READ | PROT_WRITE, MAP_PRIVATE, 0);
+    unsafe { byte(private).write_volatile(0) };
+    assert_eq!(unsafe { byte(private).read_volatile() }, 0);
+    assert_eq!(read_bytes::<4>(whole), *b"\x7fELF");
+
+    // Mappings outlive the file descriptor.
+    assert_eq!(close(fd), 0);
+    assert_eq!(read_bytes::<4>(whole), *b"\x7fELF");
+
+    assert_eq!(munmap(whole, 2 * PAGE_SIZE), 0);
+    assert_eq!(munmap(second, PAGE_SIZE), 0);
+    assert_eq!(munmap(private, PAGE_SIZE), 0);
+
+    write_shared();
+    println!("mmap_file: ok");
+    0
+}
@@ -0,0 +1,134 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use user_lib::{
+    exit, fork, getpid, kill, sigaction, sigmask, sigprocmask, waitpid, wexitstatus, wifexited, wifsignaled,
+    wtermsig, yield_, SigAction, SigInfo, CLD_EXITED, EINTR, SA_SIGINFO, SIGCHLD, SIGKILL, SIGSEGV, SIGUSR1,
+    SIGUSR2, SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_UNBLOCK,
+};
+
+static USR1_HITS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_usr1(signum: usize) {
+    assert_eq!(signum, SIGUSR1);
+    USR1_HITS.fetch_add(1, Ordering::SeqCst);
+}
+
+static CHILD_CODE: AtomicI32 = AtomicI32::new(0);
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+static CHILD_STATUS: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_chld(signum: usize, info: &SigInfo, _ucontext: usize) {
+    assert_eq!(signum, SIGCHLD);
+    assert_eq!(info.signo, SIGCHLD as i32);
+    CHILD_CODE.store(info.code, Ordering::SeqCst);
+    CHILD_PID.store(info.pid, Ordering::SeqCst);
+    CHILD_STATUS.store(info.status, Ordering::SeqCst);
+}
+
+fn handle(signum: usize, handler: usize, flags: usize) {
+    sigaction(signum, &SigAction { handler, flags, mask: 0 }).unwrap();
+}
+
+/// Waits for `pid` through any `SIGCHLD` handler interrupting the wait.
+fn wait_for(pid: isize) -> i32 {
+    loop {
+        match waitpid(pid, 0) {
+            (waited, status) if waited == pid => return status,
+            (errno, _) if errno == -EINTR => continue,
+            (errno, _) => panic!("waiting for {} failed with {}", pid, errno),
+        }
+    }
+}
+
+/// A signal sent to ourselves is handled on the way back from `kill`.
+fn handler_runs() {
+    handle(SIGUSR1, on_usr1 as *const () as usize, 0);
+    assert_eq!(kill(getpid(), SIGUSR1), 0);
+    assert_eq!(USR1_HITS.load(Ordering::SeqCst), 1);
+}
+
+/// A blocked signal stays pending until it is unblocked.
+fn blocked_signal_waits() {
+    sigprocmask(SIG_BLOCK, sigmask(SIGUSR1)).unwrap();
+    assert_eq!(kill(getpid(), SIGUSR1), 0);
+    assert_eq!(USR1_HITS.load(Ordering::SeqCst), 1);
+    sigprocmask(SIG_UNBLOCK, sigmask(SIGUSR1)).unwrap();
+    assert_eq!(USR1_HITS.load(Ordering::SeqCst), 2);
+    handle(SIGUSR1, SIG_DFL, 0);
+}
+
+fn ignored_signal_does_nothing() {
+    handle(SIGUSR2, SIG_IGN, 0);
+    assert_eq!(kill(getpid(), SIGUSR2), 0);
+    handle(SIGUSR2, SIG_DFL, 0);
+}
+
+/// `SIGCHLD` tells who exited and how.
+fn sigchld_has_siginfo() {
+    handle(SIGCHLD, on_chld as *const () as usize, SA_SIGINFO);
+    let pid = fork();
+    if pid == 0 {
+        exit(3);
+    }
+    let status = wait_for(pid);
+    assert!(wifexited(status) && wexitstatus(status) == 3);
+    assert_eq!(CHILD_CODE.load(Ordering::SeqCst), CLD_EXITED);
+    assert_eq!(CHILD_PID.load(Ordering::SeqCst), pid as i32);
+    assert_eq!(CHILD_STATUS.load(Ordering::SeqCst), 3);
+    handle(SIGCHLD, SIG_DFL, 0);
+}
+
+fn fault_kills_with_sigsegv() {
+    let pid = fork();
+    if pid == 0 {
+        unsafe { core::ptr::null_mut::<usize>().write_volatile(1) };
+        exit(0);
+    }
+    let status = wait_for(pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGSEGV as i32);
+}
+
+fn sigkill_cannot_be_caught() {
+    assert!(sigaction(SIGKILL, &SigAction { handler: on_usr1 as *const () as usize, flags: 0, mask: 0 }).is_err());
+
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    yield_();
+    assert_eq!(kill(pid, SIGKILL), 0);
+    let status = wait_for(pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGKILL as i32);
+}
+
+/// Nothing handles `SIGUSR1` here, so it ends the child.
+fn default_action_terminates() {
+    let pid = fork();
+    if pid == 0 {
+        kill(getpid(), SIGUSR1);
+        exit(0);
+    }
+    let status = wait_for(pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGUSR1 as i32);
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    handler_runs();
+    blocked_signal_waits();
+    ignored_signal_does_nothing();
+    sigchld_has_siginfo();
+    fault_kills_with_sigsegv();
+    sigkill_cannot_be_caught();
+    default_action_terminates();
+    println!("signals: ok");
+    0
+}
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE};
+
+/// More than is left free of the 128 MiB `make run` gives the machine, so
+/// this only passes with the swap disk it attaches.
+const SIZE: usize = 120 << 20;
+const PAGES: usize = SIZE / PAGE_SIZE;
+const WORDS_PER_PAGE: usize = PAGE_SIZE / 8;
+
+/// What word `i` of page `page` should hold: different on every page, and
+/// never 0, so a page that came back zeroed is caught.
+fn pattern(page: usize, i: usize) -> usize {
+    (page << 16 | i) ^ 0x5a5a_0000_0000_0001
+}
+
+fn word(addr: usize, page: usize, i: usize) -> *mut usize {
+    (addr + page * PAGE_SIZE + i * 8) as *mut usize
+}
+
+/// Words checked on each page: the first, one in the middle, the last.
+const CHECKED: [usize; 3] = [0, WORDS_PER_PAGE / 2, WORDS_PER_PAGE - 1];
+
+#[no_mangle]
+fn main(_argc: usize, _argv: *const *const u8) -> i32 {
+    let addr = mmap(0, SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    assert!(addr > 0, "mmap failed with {}", addr);
+    let addr = addr as usize;
+
+    for page in 0..PAGES {
+        for i in CHECKED {
+            unsafe { word(addr, page, i).write_volatile(pattern(page, i)) };
+        }
+    }
+    // Twice round, so pages swapped out early come back in and others go.
+    for _ in 0..2 {
+        for page in 0..PAGES {
+            for i in CHECKED {
+                let value = unsafe { word(addr, page, i).read_volatile() };
+                assert_eq!(value, pattern(page, i), "page {} word {} lost", page, i);
+            }
+        }
+    }
+
+    assert_eq!(munmap(addr, SIZE), 0);
+    println!("swap: ok");
+    0
+}
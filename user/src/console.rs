@@ -0,0 +1,32 @@
+use core::fmt::{Write, Result, Arguments};
+
+use crate::write;
+
+const STDOUT: usize = 1;
+
+struct Stdout;
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> Result {
+        write(STDOUT, s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn print(args: Arguments) {
+    Stdout.write_fmt(args).unwrap();
+}
+
+#[macro_export]
+macro_rules! print {
+    ($fmt: literal $(, $($args: tt)+)?) => {
+        $crate::console::print(format_args!($fmt $(, $($args)+)?))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    ($fmt: literal $(, $($args: tt)+)?) => {
+        $crate::console::print(format_args!(concat!($fmt, "\n") $(, $($args)+)?))
+    };
+}
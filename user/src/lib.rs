@@ -0,0 +1,331 @@
+//! What the test programs share: the entry point, console output and thin
+//! wrappers around the system calls, all following the Linux RISC-V ABI.
+
+#![no_std]
+
+#[macro_use]
+pub mod console;
+mod syscall;
+
+use core::{arch::global_asm, panic::PanicInfo, sync::atomic::{AtomicU32, Ordering}};
+
+use syscall::*;
+
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGCHLD: usize = 17;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SA_SIGINFO: usize = 0x4;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+
+pub const CLONE_VM: usize             = 0x100;
+pub const CLONE_FS: usize             = 0x200;
+pub const CLONE_FILES: usize          = 0x400;
+pub const CLONE_SIGHAND: usize        = 0x800;
+pub const CLONE_THREAD: usize         = 0x10000;
+pub const CLONE_SYSVSEM: usize        = 0x40000;
+pub const CLONE_PARENT_SETTID: usize  = 0x100000;
+pub const CLONE_CHILD_CLEARTID: usize = 0x200000;
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+
+pub const PROT_NONE: usize = 0x0;
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub const MS_SYNC: usize = 0x4;
+
+pub const AT_FDCWD: isize = -100;
+pub const O_RDONLY: u32 = 0;
+pub const O_RDWR: u32 = 2;
+
+pub const WNOHANG: usize = 1;
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub const EINTR: isize = 4;
+pub const EBADF: isize = 9;
+pub const EAGAIN: isize = 11;
+pub const EACCES: isize = 13;
+pub const EINVAL: isize = 22;
+pub const EOVERFLOW: isize = 75;
+pub const ETIMEDOUT: isize = 110;
+
+/// `struct sigaction` as the kernel takes it on riscv64.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: u64,
+}
+
+/// The start of `siginfo_t`, as far as `SIGCHLD` fills it in.
+#[repr(C)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    pub pid: i32,
+    pub uid: u32,
+    pub status: i32,
+}
+
+/// The bit of `signum` in a signal mask.
+pub const fn sigmask(signum: usize) -> u64 {
+    1 << (signum - 1)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TimeSpec {
+    pub sec: isize,
+    pub nsec: isize,
+}
+
+// The kernel leaves `argc` at `sp`, with `argv` and `envp` after it.
+global_asm!(
+    ".section .text.entry",
+    ".globl _start",
+    "_start:",
+    "    mv a0, sp",
+    "    call __start_rust",
+);
+
+// `__clone(entry, stack, flags, arg, ptid, tls, ctid)`: the new thread
+// finds `entry` and `arg` on its stack, runs `entry(arg)` and exits with
+// what it returns.
+global_asm!(
+    ".section .text",
+    ".globl __clone",
+    "__clone:",
+    "    andi a1, a1, -16",
+    "    addi a1, a1, -16",
+    "    sd a0, 0(a1)",
+    "    sd a3, 8(a1)",
+    "    mv a0, a2",
+    "    mv a2, a4",
+    "    mv a3, a5",
+    "    mv a4, a6",
+    "    li a7, 220",
+    "    ecall",
+    "    beqz a0, .Lchild",
+    "    ret",
+    ".Lchild:",
+    "    ld a1, 0(sp)",
+    "    ld a0, 8(sp)",
+    "    jalr a1",
+    "    li a7, 93",
+    "    ecall",
+);
+
+extern "C" {
+    fn __clone(
+        entry: extern "C" fn(usize) -> i32,
+        stack: usize,
+        flags: usize,
+        arg: usize,
+        ptid: *mut i32,
+        tls: usize,
+        ctid: *mut i32,
+    ) -> isize;
+}
+
+extern "Rust" {
+    fn main(argc: usize, argv: *const *const u8) -> i32;
+}
+
+#[no_mangle]
+extern "C" fn __start_rust(sp: *const usize) -> ! {
+    let argc = unsafe { sp.read() };
+    let argv = unsafe { sp.add(1) } as *const *const u8;
+    exit(unsafe { main(argc, argv) })
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    match info.location() {
+        Some(location) => println!("[{}] panicked at {}:{}: {}", getpid(), location.file(), location.line(), info.message()),
+        None => println!("[{}] panicked: {}", getpid(), info.message()),
+    }
+    exit(101)
+}
+
+/// `path` must end with a NUL.
+pub fn open(path: &str, flags: u32) -> isize {
+    assert!(path.ends_with('\0'));
+    sys_openat(AT_FDCWD, path.as_ptr(), flags)
+}
+
+pub fn close(fd: usize) -> isize {
+    sys_close(fd)
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> isize {
+    sys_read(fd, buf)
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> isize {
+    sys_write(fd, buf)
+}
+
+/// Ends the whole process, like returning from `main`.
+pub fn exit(exit_code: i32) -> ! {
+    sys_exit_group(exit_code)
+}
+
+pub fn yield_() -> isize {
+    sys_sched_yield()
+}
+
+pub fn getpid() -> isize {
+    sys_getpid()
+}
+
+pub fn getppid() -> isize {
+    sys_getppid()
+}
+
+/// Returns the child's pid in the parent and 0 in the child.
+pub fn fork() -> isize {
+    sys_clone(SIGCHLD, 0, core::ptr::null_mut(), 0, core::ptr::null_mut())
+}
+
+/// Starts a thread running `entry(arg)` on the stack ending at `stack_top`.
+/// Its tid is stored in `tid` before this returns, and cleared with a futex
+/// wake when it exits, which is what `join` waits for.
+pub fn spawn(entry: extern "C" fn(usize) -> i32, arg: usize, stack_top: usize, tid: &AtomicU32) -> isize {
+    let flags = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM
+        | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+    let tid = tid.as_ptr() as *mut i32;
+    unsafe { __clone(entry, stack_top, flags, arg, tid, 0, tid) }
+}
+
+/// Waits for the thread `spawn` stored in `tid` to exit.
+pub fn join(tid: &AtomicU32) {
+    loop {
+        let value = tid.load(Ordering::Acquire);
+        if value == 0 {
+            return;
+        }
+        futex_wait(tid, value, None);
+    }
+}
+
+/// Moves the program break, returning where it ends up; 0 just asks.
+pub fn brk(addr: usize) -> usize {
+    sys_brk(addr) as usize
+}
+
+/// Returns the address of the mapping, or the negated errno.
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: isize, offset: usize) -> isize {
+    sys_mmap(addr, len, prot, flags, fd, offset)
+}
+
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(addr, len, prot)
+}
+
+pub fn msync(addr: usize, len: usize, flags: usize) -> isize {
+    sys_msync(addr, len, flags)
+}
+
+/// `path` and every entry of `argv` must end with a NUL.
+pub fn execve(path: &str, argv: &[*const u8]) -> isize {
+    assert!(path.ends_with('\0') && argv.last() == Some(&core::ptr::null()));
+    let envp = [core::ptr::null::<u8>()];
+    sys_execve(path.as_ptr(), argv.as_ptr() as *const usize, envp.as_ptr() as *const usize)
+}
+
+/// Waits for the child `pid`, or any child if it is -1, and returns its pid
+/// with its wait status.
+pub fn waitpid(pid: isize, options: usize) -> (isize, i32) {
+    let mut status = 0;
+    let pid = sys_wait4(pid, &mut status, options);
+    (pid, status)
+}
+
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+pub fn wifsignaled(status: i32) -> bool {
+    status & 0x7f != 0 && status & 0x7f != 0x7f
+}
+
+pub fn wtermsig(status: i32) -> i32 {
+    status & 0x7f
+}
+
+pub fn gettid() -> isize {
+    sys_gettid()
+}
+
+/// Sleeps while `futex` holds `val`, for at most `timeout` if given.
+pub fn futex_wait(futex: &AtomicU32, val: u32, timeout: Option<&TimeSpec>) -> isize {
+    let timeout = timeout.map_or(0, |timeout| timeout as *const TimeSpec as usize);
+    sys_futex(futex.as_ptr(), FUTEX_WAIT | FUTEX_PRIVATE_FLAG, val, timeout, core::ptr::null(), 0)
+}
+
+/// Wakes up to `count` waiters and returns how many there were.
+pub fn futex_wake(futex: &AtomicU32, count: u32) -> isize {
+    sys_futex(futex.as_ptr(), FUTEX_WAKE | FUTEX_PRIVATE_FLAG, count, 0, core::ptr::null(), 0)
+}
+
+/// If `futex` still holds `val`, wakes `wake` of its waiters and moves up to
+/// `requeue` more over to `target`.
+pub fn futex_cmp_requeue(futex: &AtomicU32, wake: u32, requeue: usize, target: &AtomicU32, val: u32) -> isize {
+    sys_futex(futex.as_ptr(), FUTEX_CMP_REQUEUE | FUTEX_PRIVATE_FLAG, wake, requeue, target.as_ptr(), val)
+}
+
+pub fn kill(pid: isize, signum: usize) -> isize {
+    sys_kill(pid, signum)
+}
+
+/// Installs `action` for `signum` and returns the one it replaces.
+pub fn sigaction(signum: usize, action: &SigAction) -> Result<SigAction, isize> {
+    let mut old = SigAction::default();
+    match sys_rt_sigaction(signum, action, &mut old) {
+        0 => Ok(old),
+        errno => Err(errno),
+    }
+}
+
+/// Changes the signal mask as `how` says and returns the old one.
+pub fn sigprocmask(how: usize, set: u64) -> Result<u64, isize> {
+    let mut old = 0;
+    match sys_rt_sigprocmask(how, &set, &mut old) {
+        0 => Ok(old),
+        errno => Err(errno),
+    }
+}
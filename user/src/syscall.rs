@@ -0,0 +1,130 @@
+use core::arch::asm;
+
+use crate::SigAction;
+
+const SIGSET_SIZE: usize = core::mem::size_of::<u64>();
+
+pub const SYS_OPENAT: usize      = 56;
+pub const SYS_CLOSE: usize       = 57;
+pub const SYS_READ: usize        = 63;
+pub const SYS_WRITE: usize       = 64;
+pub const SYS_EXIT_GROUP: usize  = 94;
+pub const SYS_FUTEX: usize       = 98;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_KILL: usize        = 129;
+pub const SYS_RT_SIGACTION: usize   = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_GETPID: usize      = 172;
+pub const SYS_GETPPID: usize     = 173;
+pub const SYS_GETTID: usize      = 178;
+pub const SYS_BRK: usize         = 214;
+pub const SYS_MUNMAP: usize      = 215;
+pub const SYS_CLONE: usize       = 220;
+pub const SYS_EXECVE: usize      = 221;
+pub const SYS_MMAP: usize        = 222;
+pub const SYS_MPROTECT: usize    = 226;
+pub const SYS_MSYNC: usize       = 227;
+pub const SYS_WAIT4: usize       = 260;
+
+/// Makes a Linux-style system call, returning the raw result.
+pub fn syscall(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a7") id,
+        );
+    }
+    ret
+}
+
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    syscall(SYS_OPENAT, [dirfd as usize, path as usize, flags as usize, 0, 0, 0])
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    syscall(SYS_CLOSE, [fd, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_read(fd: usize, buf: &mut [u8]) -> isize {
+    syscall(SYS_READ, [fd, buf.as_mut_ptr() as usize, buf.len(), 0, 0, 0])
+}
+
+pub fn sys_write(fd: usize, buf: &[u8]) -> isize {
+    syscall(SYS_WRITE, [fd, buf.as_ptr() as usize, buf.len(), 0, 0, 0])
+}
+
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    syscall(SYS_EXIT_GROUP, [exit_code as usize, 0, 0, 0, 0, 0]);
+    unreachable!("sys_exit_group returned");
+}
+
+pub fn sys_futex(uaddr: *const u32, futex_op: usize, val: u32, timeout: usize, uaddr2: *const u32, val3: u32) -> isize {
+    syscall(SYS_FUTEX, [uaddr as usize, futex_op, val as usize, timeout, uaddr2 as usize, val3 as usize])
+}
+
+pub fn sys_sched_yield() -> isize {
+    syscall(SYS_SCHED_YIELD, [0; 6])
+}
+
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    syscall(SYS_KILL, [pid as usize, signum, 0, 0, 0, 0])
+}
+
+pub fn sys_rt_sigaction(signum: usize, act: *const SigAction, oldact: *mut SigAction) -> isize {
+    syscall(SYS_RT_SIGACTION, [signum, act as usize, oldact as usize, SIGSET_SIZE, 0, 0])
+}
+
+pub fn sys_rt_sigprocmask(how: usize, set: *const u64, oldset: *mut u64) -> isize {
+    syscall(SYS_RT_SIGPROCMASK, [how, set as usize, oldset as usize, SIGSET_SIZE, 0, 0])
+}
+
+pub fn sys_getpid() -> isize {
+    syscall(SYS_GETPID, [0; 6])
+}
+
+pub fn sys_getppid() -> isize {
+    syscall(SYS_GETPPID, [0; 6])
+}
+
+pub fn sys_gettid() -> isize {
+    syscall(SYS_GETTID, [0; 6])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYS_BRK, [addr, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYS_MUNMAP, [addr, len, 0, 0, 0, 0])
+}
+
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: isize, offset: usize) -> isize {
+    syscall(SYS_MMAP, [addr, len, prot, flags, fd as usize, offset])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    syscall(SYS_MPROTECT, [addr, len, prot, 0, 0, 0])
+}
+
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> isize {
+    syscall(SYS_MSYNC, [addr, len, flags, 0, 0, 0])
+}
+
+pub fn sys_clone(flags: usize, stack: usize, ptid: *mut i32, tls: usize, ctid: *mut i32) -> isize {
+    syscall(SYS_CLONE, [flags, stack, ptid as usize, tls, ctid as usize, 0])
+}
+
+pub fn sys_execve(path: *const u8, argv: *const usize, envp: *const usize) -> isize {
+    syscall(SYS_EXECVE, [path as usize, argv as usize, envp as usize, 0, 0, 0])
+}
+
+pub fn sys_wait4(pid: isize, wstatus: *mut i32, options: usize) -> isize {
+    syscall(SYS_WAIT4, [pid as usize, wstatus as usize, options, 0, 0, 0])
+}